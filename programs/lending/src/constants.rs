@@ -1,9 +1,30 @@
 use anchor_lang::prelude::*;
 
+// 定点数精度 1e18, 利率指数、每秒利率和usd价值都以 WAD 表示
 #[constant]
-pub const SOL_USD_FEED_ID: &str =
-    "0xef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d";
-pub const USDC_USD_FEED_ID: &str =
-    "0xeaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a";
-pub const MAX_AGE: u64 = 100;
-pub const PRICE_DECIMALS: u64 = 8;
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+pub const WAD_DECIMALS: i32 = 18;
+
+// 核销坏账后supply_index低于该值时bank被标记为资不抵债, 不再接受存款,
+// 避免新存款按极小的指数换算出溢出u64的份额, 或者被旧份额取走
+pub const MIN_SUPPLY_INDEX: u128 = WAD / 1_000;
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+// 利用率100%时借款年化利率的上限(bps), 限制长时间没有更新的bank一次累计的利息
+pub const MAX_BORROW_RATE: u64 = 100_000;
+
+// 每个用户最多同时持有的position数量
+pub const MAX_POSITIONS: usize = 8;
+
+// 一个market最多可以上架的bank数量
+pub const MAX_BANKS: usize = 32;
+
+// 一个market最多可以定义的e-mode类别数量
+pub const MAX_EMODE_CATEGORIES: usize = 8;
+
+// market和bank的暂停标志位, 任意一层设置后对应操作都会被拒绝
+pub const PAUSE_DEPOSIT: u8 = 1 << 0;
+pub const PAUSE_BORROW: u8 = 1 << 1;
+pub const PAUSE_WITHDRAW: u8 = 1 << 2;
+pub const PAUSE_REPAY: u8 = 1 << 3;
+pub const PAUSE_LIQUIDATE: u8 = 1 << 4;
+pub const PAUSE_FLASH_LOAN: u8 = 1 << 5;
@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 
+// 新增的错误码只能追加在末尾, 避免改变已有错误码的数值
 #[error_code]
 pub enum ErrorCode {
     #[msg("Insufficient funds")]
@@ -12,4 +13,98 @@ pub enum ErrorCode {
     NotUnderCollateralized,
     #[msg("Math overflow")]
     MathOverflow,
+    #[msg("Withdrawal would leave the position below its max LTV")]
+    WithdrawExceedsMaxLtv,
+    #[msg("Invalid interest rate model")]
+    InvalidInterestRateModel,
+    #[msg("Invalid reserve factor")]
+    InvalidReserveFactor,
+    #[msg("max_ltv must be below liquidation_threshold, which must not exceed 100%")]
+    InvalidLtvConfig,
+    #[msg("Liquidation bonus cannot be paid out of collateral at the liquidation threshold")]
+    InvalidLiquidationBonus,
+    #[msg("Invalid liquidation close factor")]
+    InvalidCloseFactor,
+    #[msg("Signer is not the market admin")]
+    Unauthorized,
+    #[msg("Signer is neither the market admin nor the guardian")]
+    NotAdminOrGuardian,
+    #[msg("Operation is paused")]
+    OperationPaused,
+    #[msg("Market has reached the maximum number of banks")]
+    TooManyBanks,
+    #[msg("Bank is not listed in the market")]
+    BankNotListed,
+    #[msg("No protocol fees to collect")]
+    NoFeesToCollect,
+    #[msg("User has no position in this bank")]
+    PositionNotFound,
+    #[msg("User has reached the maximum number of positions")]
+    TooManyPositions,
+    #[msg("Bank or price account missing from remaining accounts")]
+    MissingRemainingAccount,
+    #[msg("Invalid bank account")]
+    InvalidBankAccount,
+    #[msg("Invalid price update account")]
+    InvalidPriceAccount,
+    #[msg("Invalid oracle config")]
+    InvalidOracleConfig,
+    #[msg("Oracle returned a non-positive price")]
+    InvalidPrice,
+    #[msg("Oracle confidence interval is too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Deposit would exceed the bank supply cap")]
+    SupplyCapExceeded,
+    #[msg("Borrow would exceed the bank borrow cap")]
+    BorrowCapExceeded,
+    #[msg("Collateral received is below min_collateral_out")]
+    LiquidationSlippageExceeded,
+    #[msg("User still has deposits, debt is not bad debt")]
+    UserHasCollateral,
+    #[msg("Flash loan fee must not exceed 100%")]
+    InvalidFlashLoanFee,
+    #[msg("A flash loan is already in progress for this bank")]
+    FlashLoanInProgress,
+    #[msg("No flash loan in progress for this bank")]
+    FlashLoanNotActive,
+    #[msg("Matching flash_repay not found later in the transaction")]
+    FlashRepayNotFound,
+    #[msg("Flash loans cannot be taken through CPI")]
+    FlashLoanCpiNotAllowed,
+    #[msg("Native SOL instructions require the wSOL mint")]
+    NotNativeMint,
+    #[msg("Position is not enabled as collateral")]
+    CollateralDisabled,
+    #[msg("Disabling collateral would exceed max LTV")]
+    DisableCollateralExceedsMaxLtv,
+    #[msg("Isolated collateral cannot be combined with other collateral")]
+    IsolationModeConflict,
+    #[msg("Asset cannot be borrowed in isolation mode")]
+    IsolationBorrowNotAllowed,
+    #[msg("Borrow would exceed the isolated asset debt ceiling")]
+    IsolatedDebtCeilingExceeded,
+    #[msg("Isolated bank account is missing or does not match")]
+    InvalidIsolatedBank,
+    #[msg("Too many e-mode categories in this market")]
+    TooManyEmodeCategories,
+    #[msg("Invalid e-mode category")]
+    InvalidEmodeCategory,
+    #[msg("Borrowed asset is not in the user's e-mode category")]
+    EmodeCategoryMismatch,
+    #[msg("Changing e-mode category would exceed max LTV")]
+    EmodeChangeExceedsMaxLtv,
+    #[msg("Collateral and borrowed bank must be different")]
+    SameBankLiquidation,
+    #[msg("Asset tier cannot be changed while the bank has deposits")]
+    AssetTierChangeNotAllowed,
+    #[msg("Bank is insolvent after bad debt writeoff")]
+    BankInsolvent,
+    #[msg("Bank is not insolvent")]
+    BankNotInsolvent,
+    #[msg("Bank still has deposit shares outstanding")]
+    BankHasDeposits,
+    #[msg("Only stablecoins can be borrowable in isolation mode")]
+    InvalidIsolationConfig,
+    #[msg("Native SOL instructions require the temporary wSOL account")]
+    MissingNativeTokenAccount,
 }
@@ -0,0 +1,218 @@
+use anchor_lang::prelude::*;
+
+use crate::state::{BankConfig, EmodeCategory, OracleConfig};
+
+#[event]
+pub struct MarketInitialized {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+}
+
+#[event]
+pub struct MarketAdminUpdated {
+    pub old_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+#[event]
+pub struct GuardianUpdated {
+    pub old_guardian: Pubkey,
+    pub new_guardian: Pubkey,
+}
+
+// bank为None时是整个市场的暂停标志
+#[event]
+pub struct PauseFlagsUpdated {
+    pub bank: Option<Pubkey>,
+    pub signer: Pubkey,
+    pub old_flags: u8,
+    pub new_flags: u8,
+}
+
+#[event]
+pub struct BankInitialized {
+    pub bank: Pubkey,
+    pub mint: Pubkey,
+    pub mint_decimals: u8,
+    pub config: BankConfig,
+    pub oracle: OracleConfig,
+}
+
+#[event]
+pub struct OracleUpdated {
+    pub bank: Pubkey,
+    pub old: OracleConfig,
+    pub new: OracleConfig,
+}
+
+#[event]
+pub struct ProtocolFeesCollected {
+    pub bank: Pubkey,
+    pub fee_token_account: Pubkey,
+    pub amount: u64,
+    pub total_reserves: u64,
+}
+
+#[event]
+pub struct BankRefreshed {
+    pub bank: Pubkey,
+    pub supply_index: u128,
+    pub borrow_index: u128,
+    pub total_deposits: u64,
+    pub total_borrowed: u64,
+    pub total_reserves: u64,
+}
+
+#[event]
+pub struct UserInitialized {
+    pub user: Pubkey,
+    pub owner: Pubkey,
+}
+
+#[event]
+pub struct Deposit {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub supply_index: u128,
+    pub total_deposits: u64,
+    pub total_deposit_shares: u64,
+}
+
+// 用户有借款时取款会经过健康度检查, 此时记录该bank使用的抵押物价格, 否则为0
+#[event]
+pub struct Withdraw {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub price: u64,
+    pub price_exponent: i32,
+    pub supply_index: u128,
+    pub total_deposits: u64,
+    pub total_deposit_shares: u64,
+}
+
+#[event]
+pub struct Borrow {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub price: u64,
+    pub price_exponent: i32,
+    pub borrow_index: u128,
+    pub total_borrowed: u64,
+    pub total_borrowed_shares: u64,
+}
+
+#[event]
+pub struct Repay {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub borrow_index: u128,
+    pub total_borrowed: u64,
+    pub total_borrowed_shares: u64,
+}
+
+#[event]
+pub struct Liquidation {
+    pub liquidator: Pubkey,
+    pub user: Pubkey,
+    pub collateral_bank: Pubkey,
+    pub borrowed_bank: Pubkey,
+    pub repaid_amount: u64,
+    pub repaid_shares: u64,
+    pub seized_amount: u64,
+    pub seized_shares: u64,
+    pub collateral_price: u64,
+    pub collateral_price_exponent: i32,
+    pub borrowed_price: u64,
+    pub borrowed_price_exponent: i32,
+    pub health_factor: u64,
+    pub liquidation_bonus: u64,
+    pub collateral_total_deposits: u64,
+    pub collateral_total_deposit_shares: u64,
+    pub borrowed_total_borrowed: u64,
+    pub borrowed_total_borrowed_shares: u64,
+}
+
+#[event]
+pub struct EmodeCategoryUpdated {
+    pub category: EmodeCategory,
+}
+
+#[event]
+pub struct UserEmodeUpdated {
+    pub user: Pubkey,
+    pub old_category: u8,
+    pub new_category: u8,
+}
+
+#[event]
+pub struct CollateralToggled {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub enabled: bool,
+}
+
+// 存款份额从position转出为钱包中的receipt token
+#[event]
+pub struct ReceiptMinted {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub shares: u64,
+    pub supply_index: u128,
+}
+
+// 钱包中的receipt token存回position
+#[event]
+pub struct ReceiptRedeemed {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub shares: u64,
+    pub supply_index: u128,
+}
+
+#[event]
+pub struct FlashLoan {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub supply_index: u128,
+    pub total_reserves: u64,
+}
+
+#[event]
+pub struct BadDebtWrittenOff {
+    pub bank: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub netted: u64, //用户在同一个bank中的存款抵扣的借款
+    pub loss: u64,
+    pub covered_by_reserves: u64,
+    pub insolvent: bool,
+    pub old_supply_index: u128,
+    pub new_supply_index: u128,
+    pub total_deposits: u64,
+    pub total_borrowed: u64,
+}
+
+#[event]
+pub struct BankRelisted {
+    pub bank: Pubkey,
+    pub total_borrowed: u64,
+    pub total_reserves: u64,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub bank: Pubkey,
+    pub old: BankConfig,
+    pub new: BankConfig,
+}
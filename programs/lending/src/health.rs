@@ -0,0 +1,228 @@
+use anchor_lang::prelude::*;
+
+use crate::constants::WAD_DECIMALS;
+use crate::error::ErrorCode;
+use crate::oracle::{check_confidence, get_price, OraclePrice};
+use crate::state::{Bank, EmodeCategory, User};
+use crate::utils::load_bank_and_price;
+
+// 用户所有position汇总后的健康状况, 所有价值都是以WAD表示的usd
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Health {
+    pub collateral_value: u128,  //抵押物总价值
+    pub borrowable_value: u128,  //按每个bank的max_ltv加权后的抵押物价值
+    pub liquidation_value: u128, //按每个bank的liquidation_threshold加权后的抵押物价值
+    pub liability_value: u128,   //借款总价值(包括利息)
+}
+
+impl Health {
+    // 借款或取款之后必须满足: 借款总价值 <= 按max_ltv加权的抵押物价值
+    pub fn is_within_max_ltv(&self) -> bool {
+        self.liability_value <= self.borrowable_value
+    }
+
+    // 借款总价值 > 按liquidation_threshold加权的抵押物价值时可以被清算
+    pub fn is_liquidatable(&self) -> bool {
+        self.liability_value > self.liquidation_value
+    }
+
+    // 健康因子, 单位为bps, 没有借款时为u64::MAX
+    pub fn health_factor(&self) -> Result<u64> {
+        if self.liability_value == 0 {
+            return Ok(u64::MAX);
+        }
+        let health_factor = self
+            .liquidation_value
+            .checked_mul(10_000)
+            .ok_or(ErrorCode::MathOverflow)?
+            / self.liability_value;
+        Ok(u64::try_from(health_factor).unwrap_or(u64::MAX))
+    }
+}
+
+// 属于用户所选e-mode类别的bank使用类别的max_ltv和liquidation_threshold, 否则使用bank自己的
+pub fn risk_params(bank: &Bank, emode: Option<&EmodeCategory>) -> (u64, u64) {
+    match emode.filter(|category| category.id == bank.emode_category) {
+        Some(category) => (category.max_ltv, category.liquidation_threshold),
+        None => (bank.max_ltv, bank.liquidation_threshold),
+    }
+}
+
+// 计算用户所有存款和借款的健康状况
+// remaining_accounts 需要为用户的每个position按 [bank, price_update] 成对传入
+// loaded_banks 为本指令中已经加载并修改过的bank, 优先使用它们的内存状态
+// emode 为用户所选的e-mode类别
+// strict 为true时(借款/取款)要求每个价格的置信区间都不超过bank的配置
+pub fn compute_health(
+    user: &User,
+    remaining_accounts: &[AccountInfo],
+    loaded_banks: &[(Pubkey, &Bank)],
+    emode: Option<&EmodeCategory>,
+    strict: bool,
+) -> Result<Health> {
+    let now = Clock::get()?.unix_timestamp;
+    let mut health = Health::default();
+
+    for position in user.positions.iter() {
+        // 不作为抵押物的存款不影响健康度, 也不需要传入价格
+        let counts_as_collateral = position.deposit_shares > 0 && position.collateral_enabled;
+        if !counts_as_collateral && position.borrow_shares == 0 {
+            continue;
+        }
+
+        let (mut bank, price_update) = load_bank_and_price(remaining_accounts, &position.bank)?;
+        match loaded_banks.iter().find(|(key, _)| *key == position.bank) {
+            Some((_, loaded)) => bank = (*loaded).clone(),
+            // 其他bank可能已经有一段时间没有更新, 在内存中计算到当前时间
+            None => bank.accrue_interest(now)?,
+        }
+        let price = get_price(&bank, &price_update)?;
+        if strict {
+            check_confidence(&bank, &price)?;
+        }
+
+        if counts_as_collateral {
+            let deposited = bank.deposit_amount(position.deposit_shares)?;
+            let value = usd_value(
+                deposited,
+                price.collateral_price()?,
+                price.exponent,
+                bank.mint_decimals,
+            )?;
+            let (max_ltv, liquidation_threshold) = risk_params(&bank, emode);
+            health.collateral_value = add(health.collateral_value, value)?;
+            health.borrowable_value = add(health.borrowable_value, apply_bps(value, max_ltv)?)?;
+            health.liquidation_value = add(
+                health.liquidation_value,
+                apply_bps(value, liquidation_threshold)?,
+            )?;
+        }
+
+        if position.borrow_shares > 0 {
+            let borrowed = bank.borrow_amount(position.borrow_shares)?;
+            health.liability_value = add(
+                health.liability_value,
+                usd_value(
+                    borrowed,
+                    price.liability_price()?,
+                    price.exponent,
+                    bank.mint_decimals,
+                )?,
+            )?;
+        }
+    }
+
+    Ok(health)
+}
+
+// 在满足max_ltv的前提下, 用户最多可以从bank中取出的数量
+pub fn max_withdrawable(
+    health: &Health,
+    bank: &Bank,
+    emode: Option<&EmodeCategory>,
+    deposited: u64,
+    price: &OraclePrice,
+) -> Result<u64> {
+    let (max_ltv, _) = risk_params(bank, emode);
+    if health.liability_value == 0 || max_ltv == 0 {
+        return Ok(deposited);
+    }
+    if !health.is_within_max_ltv() {
+        return Ok(0);
+    }
+    // 剩余的加权价值 / max_ltv = 可以取出的抵押物价值
+    let slack = health.borrowable_value - health.liability_value;
+    let withdrawable_value =
+        slack.checked_mul(10_000).ok_or(ErrorCode::MathOverflow)? / max_ltv as u128;
+    let max_amount = token_amount(
+        withdrawable_value,
+        price.collateral_price()?,
+        price.exponent,
+        bank.mint_decimals,
+    )?;
+    Ok(max_amount.min(deposited))
+}
+
+// 将代币数量换算成以WAD表示的usd价值
+// value = amount / 10^decimals * price * 10^exponent * WAD
+pub fn usd_value(amount: u64, price: u64, exponent: i32, decimals: u8) -> Result<u128> {
+    let value = (amount as u128)
+        .checked_mul(price as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let scale = WAD_DECIMALS + exponent - decimals as i32;
+    if scale >= 0 {
+        value
+            .checked_mul(pow10(scale as u32)?)
+            .ok_or(ErrorCode::MathOverflow.into())
+    } else {
+        Ok(value / pow10((-scale) as u32)?)
+    }
+}
+
+// 隔离模式下可借出的都是稳定币, 借款按面值(1美元)计入隔离资产的总借款
+pub fn nominal_usd_value(amount: u64, decimals: u8) -> Result<u128> {
+    usd_value(amount, 1, 0, decimals)
+}
+
+// usd_value 的逆运算, 将以WAD表示的usd价值换算成代币数量(向下取整)
+pub fn token_amount(value: u128, price: u64, exponent: i32, decimals: u8) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidPrice);
+    let scale = WAD_DECIMALS + exponent - decimals as i32;
+    let amount = if scale >= 0 {
+        value
+            / (price as u128)
+                .checked_mul(pow10(scale as u32)?)
+                .ok_or(ErrorCode::MathOverflow)?
+    } else {
+        value
+            .checked_mul(pow10((-scale) as u32)?)
+            .ok_or(ErrorCode::MathOverflow)?
+            / price as u128
+    };
+    Ok(u64::try_from(amount).unwrap_or(u64::MAX))
+}
+
+fn pow10(exponent: u32) -> Result<u128> {
+    10u128
+        .checked_pow(exponent)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+fn apply_bps(value: u128, bps: u64) -> Result<u128> {
+    value
+        .checked_mul(bps as u128)
+        .map(|v| v / 10_000)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+fn add(a: u128, b: u128) -> Result<u128> {
+    a.checked_add(b).ok_or(ErrorCode::MathOverflow.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::WAD;
+
+    #[test]
+    fn usd_value_normalizes_exponent_and_decimals() {
+        // 1.5 SOL (9位小数), 价格 150.00000000 (exponent -8) -> 225美元
+        let value = usd_value(1_500_000_000, 15_000_000_000, -8, 9).unwrap();
+        assert_eq!(value, 225 * WAD);
+        // 2 USDC (6位小数), 价格 0.99 (exponent -2)
+        assert_eq!(usd_value(2_000_000, 99, -2, 6).unwrap(), WAD * 198 / 100);
+        assert_eq!(nominal_usd_value(2_000_000, 6).unwrap(), 2 * WAD);
+    }
+
+    #[test]
+    fn token_amount_inverts_usd_value() {
+        let value = usd_value(1_500_000_000, 15_000_000_000, -8, 9).unwrap();
+        assert_eq!(
+            token_amount(value, 15_000_000_000, -8, 9).unwrap(),
+            1_500_000_000
+        );
+        // 向下取整: 1美元按3美元的价格换算
+        assert_eq!(token_amount(WAD, 3, 0, 6).unwrap(), 333_333);
+        assert!(token_amount(WAD, 0, 0, 6).is_err());
+    }
+}
@@ -1,12 +1,93 @@
+use crate::constants::*;
+use crate::error::ErrorCode;
+use crate::events::*;
+use crate::program::Lending;
 use crate::state::*;
 use anchor_lang::prelude::*;
-use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use anchor_spl::token_interface::{
+    transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
 
+#[derive(Accounts)]
+pub struct InitMarket<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + LendingMarket::INIT_SPACE,
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    // 市场是全局唯一的, 只有程序的升级权限账户可以初始化, 防止部署后被抢先初始化
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, Lending>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"lending_market"],
+        bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    pub admin: Signer<'info>,
+}
+
+// guardian或admin可以暂停整个market的某些操作
+#[derive(Accounts)]
+pub struct SetMarketPauseFlags<'info> {
+    #[account(
+        mut,
+        seeds = [b"lending_market"],
+        bump,
+        constraint = market.is_admin_or_guardian(&signer.key()) @ ErrorCode::NotAdminOrGuardian,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    pub signer: Signer<'info>,
+}
+
+// guardian或admin可以暂停单个bank的某些操作
+#[derive(Accounts)]
+pub struct SetBankPauseFlags<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+        constraint = market.is_admin_or_guardian(&signer.key()) @ ErrorCode::NotAdminOrGuardian,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub signer: Signer<'info>,
+}
+
+// 只有market的admin可以上架新的bank
 #[derive(Accounts)]
 pub struct InitBank<'info> {
+    #[account(
+        mut,
+        seeds = [b"lending_market"],
+        bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, LendingMarket>,
     #[account(
         init,
-        payer = payer,
+        payer = admin,
         space = 8 + Bank::INIT_SPACE,
         seeds = [mint.key().as_ref()],
         bump,
@@ -18,12 +99,32 @@ pub struct InitBank<'info> {
         bump,
         token::mint = mint,
         token::authority = bank_token_account,
-        payer = payer,
+        payer = admin,
     )]
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    // 可转让的存款凭证, 1个receipt token对应1份存款份额
+    #[account(
+        init,
+        seeds = [b"receipt", mint.key().as_ref()],
+        bump,
+        mint::decimals = mint.decimals,
+        mint::authority = receipt_mint,
+        payer = admin,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    // 保存仍然记在用户position中的份额对应的receipt token
+    #[account(
+        init,
+        seeds = [b"receipt_vault", mint.key().as_ref()],
+        bump,
+        token::mint = receipt_mint,
+        token::authority = receipt_vault,
+        payer = admin,
+    )]
+    pub receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     pub mint: InterfaceAccount<'info, Mint>,
     #[account(mut)]
-    pub payer: Signer<'info>,
+    pub admin: Signer<'info>,
     pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
@@ -43,27 +144,307 @@ pub struct InitUser<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [b"treasury", mint.key().as_ref()],
+        bump,
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub fee_token_account: InterfaceAccount<'info, TokenAccount>, //接收协议收入的账户
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateBankOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"lending_market"],
+        bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateBankConfig<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+        has_one = admin @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub admin: Signer<'info>,
+}
+
+pub fn process_init_market(ctx: Context<InitMarket>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.admin = ctx.accounts.admin.key();
+
+    emit!(MarketInitialized {
+        market: market.key(),
+        admin: market.admin,
+    });
+    Ok(())
+}
+
+pub fn process_set_market_admin(ctx: Context<UpdateMarket>, new_admin: Pubkey) -> Result<()> {
+    let old_admin = ctx.accounts.market.admin;
+    ctx.accounts.market.admin = new_admin;
+
+    emit!(MarketAdminUpdated {
+        old_admin,
+        new_admin,
+    });
+    Ok(())
+}
+
+pub fn process_set_guardian(ctx: Context<UpdateMarket>, guardian: Pubkey) -> Result<()> {
+    let old_guardian = ctx.accounts.market.guardian;
+    ctx.accounts.market.guardian = guardian;
+
+    emit!(GuardianUpdated {
+        old_guardian,
+        new_guardian: guardian,
+    });
+    Ok(())
+}
+
+pub fn process_set_emode_category(
+    ctx: Context<UpdateMarket>,
+    category: EmodeCategory,
+) -> Result<()> {
+    ctx.accounts.market.set_emode_category(category)?;
+    emit!(EmodeCategoryUpdated { category });
+    Ok(())
+}
+
+pub fn process_set_market_pause_flags(ctx: Context<SetMarketPauseFlags>, flags: u8) -> Result<()> {
+    let old_flags = ctx.accounts.market.pause_flags;
+    ctx.accounts.market.pause_flags = flags;
+
+    emit!(PauseFlagsUpdated {
+        bank: None,
+        signer: ctx.accounts.signer.key(),
+        old_flags,
+        new_flags: flags,
+    });
+    Ok(())
+}
+
+pub fn process_set_bank_pause_flags(ctx: Context<SetBankPauseFlags>, flags: u8) -> Result<()> {
+    let old_flags = ctx.accounts.bank.pause_flags;
+    ctx.accounts.bank.pause_flags = flags;
+
+    emit!(PauseFlagsUpdated {
+        bank: Some(ctx.accounts.bank.key()),
+        signer: ctx.accounts.signer.key(),
+        old_flags,
+        new_flags: flags,
+    });
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn process_init_bank(
     ctx: Context<InitBank>,
     liquidation_threshold: u64,
     liquidation_close_factor: u64,
     liquidation_bonus: u64,
     max_ltv: u64,
+    interest_rate_model: InterestRateModel,
+    reserve_factor: u64,
+    oracle: OracleConfig,
 ) -> Result<()> {
+    let config = BankConfig {
+        max_ltv,
+        liquidation_threshold,
+        liquidation_bonus,
+        liquidation_close_factor,
+        reserve_factor,
+        interest_rate_model,
+        ..Default::default()
+    };
+    config.validate()?;
+    oracle.validate()?;
+
+    let bank_key = ctx.accounts.bank.key();
+    let market_key = ctx.accounts.market.key();
+    ctx.accounts.market.add_bank(BankEntry {
+        bank: bank_key,
+        mint: ctx.accounts.mint.key(),
+        oracle,
+    })?;
+
     let bank = &mut ctx.accounts.bank;
+    bank.market = market_key;
     bank.mint_address = ctx.accounts.mint.key();
-    bank.authority = ctx.accounts.payer.key();
-    bank.liquidation_threshold = liquidation_threshold;
-    bank.max_ltv = max_ltv;
-    bank.interest_rate = (0.05 / (365.0 * 24.0 * 60.0 * 60.0)) as u64; //年化收益率5% 转换为每秒的收益率
-    bank.liquidation_close_factor = liquidation_close_factor;
-    bank.liquidation_bonus = liquidation_bonus;
+    bank.mint_decimals = ctx.accounts.mint.decimals;
+    bank.set_config(&config);
+    bank.oracle = oracle;
+    bank.supply_index = WAD;
+    bank.borrow_index = WAD;
+    bank.last_updated = Clock::get()?.unix_timestamp;
+
+    emit!(BankInitialized {
+        bank: bank_key,
+        mint: bank.mint_address,
+        mint_decimals: bank.mint_decimals,
+        config,
+        oracle,
+    });
     Ok(())
 }
 
-pub fn process_init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
+pub fn process_init_user(ctx: Context<InitUser>) -> Result<()> {
     let user = &mut ctx.accounts.user;
     user.owner = ctx.accounts.payer.key();
-    user.usdc_address = usdc_address;
+
+    emit!(UserInitialized {
+        user: user.key(),
+        owner: user.owner,
+    });
+    Ok(())
+}
+
+pub fn process_update_bank_config(
+    ctx: Context<UpdateBankConfig>,
+    args: BankConfigArgs,
+) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    // 先按旧的利率把利息计算到当前时间
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+    let old = bank.config();
+    let new = args.apply(&old);
+    new.validate()?;
+    // 用户的隔离状态在启用抵押时确定, 已有存款的bank修改资产等级会让这些用户绕过隔离模式和借款上限
+    require!(
+        new.asset_tier == old.asset_tier || bank.total_deposit_shares == 0,
+        ErrorCode::AssetTierChangeNotAllowed
+    );
+    bank.set_config(&new);
+
+    emit!(ConfigUpdated {
+        bank: bank.key(),
+        old,
+        new,
+    });
+    Ok(())
+}
+
+// 资不抵债的bank在所有旧份额都被移除之后重新上架, 指数从1重新开始
+pub fn process_relist_bank(ctx: Context<UpdateBankConfig>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    require!(bank.insolvent, ErrorCode::BankNotInsolvent);
+    require!(bank.total_deposit_shares == 0, ErrorCode::BankHasDeposits);
+
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    bank.supply_index = WAD;
+    bank.total_depoists = 0;
+    bank.insolvent = false;
+
+    emit!(BankRelisted {
+        bank: bank.key(),
+        total_borrowed: bank.total_borrowed,
+        total_reserves: bank.total_reserves,
+    });
+    Ok(())
+}
+
+pub fn process_update_bank_oracle(
+    ctx: Context<UpdateBankOracle>,
+    oracle: OracleConfig,
+) -> Result<()> {
+    oracle.validate()?;
+    let bank_key = ctx.accounts.bank.key();
+    let old = ctx.accounts.bank.oracle;
+    ctx.accounts.market.find_bank_mut(&bank_key)?.oracle = oracle;
+    ctx.accounts.bank.oracle = oracle;
+
+    emit!(OracleUpdated {
+        bank: bank_key,
+        old,
+        new: oracle,
+    });
+    Ok(())
+}
+
+pub fn process_collect_protocol_fees(ctx: Context<CollectProtocolFees>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+    // 只能提取treasury中当前未被借出的部分
+    let amount = bank
+        .total_reserves
+        .min(ctx.accounts.bank_token_account.amount);
+    require!(amount > 0, ErrorCode::NoFeesToCollect);
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.fee_token_account.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, transfer_cpi_accounts, signer_seeds);
+
+    transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.total_reserves -= amount;
+
+    emit!(ProtocolFeesCollected {
+        bank: bank.key(),
+        fee_token_account: ctx.accounts.fee_token_account.key(),
+        amount,
+        total_reserves: bank.total_reserves,
+    });
     Ok(())
 }
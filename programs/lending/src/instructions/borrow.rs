@@ -3,20 +3,36 @@ use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
 
 use crate::constants::*;
 use crate::error::ErrorCode;
+use crate::events;
+use crate::health::{compute_health, nominal_usd_value};
+use crate::oracle::get_price;
 use crate::state::*;
-use crate::utils::calculate_collateral_interest;
+use crate::utils::{
+    check_native_mint, close_if_empty, load_bank_and_price, token_balance, unwrap_sol,
+};
 #[derive(Accounts)]
 pub struct Borrow<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
     #[account(
         mut,
         seeds = [mint.key().as_ref()],
-        bump
+        bump,
+        has_one = market,
     )]
     pub bank: Account<'info, Bank>,
+    // 用户处于隔离模式时需要传入作为抵押物的隔离资产bank, 用于记录以它为抵押的总借款
+    #[account(
+        mut,
+        has_one = market,
+    )]
+    pub isolated_bank: Option<Box<Account<'info, Bank>>>,
     #[account(
         mut,
         seeds = [b"treasury",mint.key().as_ref()],
@@ -37,8 +53,14 @@ pub struct Borrow<'info> {
         associated_token::token_program = token_program
     )]
     pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: 只有原生SOL的版本需要, 通过seeds约束确认地址, 在指令内创建并关闭的临时wSOL账户
+    #[account(
+        mut,
+        seeds = [b"native", payer.key().as_ref()],
+        bump,
+    )]
+    pub native_token_account: Option<UncheckedAccount<'info>>,
     pub mint: InterfaceAccount<'info, Mint>,
-    pub sol_or_usdc_price_feed: Account<'info, PriceUpdateV2>, //使用pyth oracles 来更新价格
     #[account(mut)]
     pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
@@ -46,64 +68,62 @@ pub struct Borrow<'info> {
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+// remaining_accounts: 为用户的每个position(包括本次借出的bank)按 [bank, price_update] 成对传入,
+// 使用pyth oracles 来获取价格
 pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_BORROW)?;
     let bank = &mut ctx.accounts.bank;
-    let user = &mut ctx.accounts.user;
-
-    let sol_or_usdc_price_feed = &mut ctx.accounts.sol_or_usdc_price_feed;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    let bank_key = bank.key();
 
-    //抵押物的价值 (以usd为单位)
-    let total_collateral_in_usd: u64;
-    // 用户想要借出的金额(以usd为单位)
-    let amount_in_usd: u64;
+    let user_shares = bank.borrow_shares_for(amount)?;
 
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            // 这里的mint为usdc这表明用户想要用deposited的sol换取usdc,所以就要计算的当前sol的价值
-            let sol_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID)?;
-            let sol_price = sol_or_usdc_price_feed.get_price_no_older_than(
-                &Clock::get()?,
-                MAX_AGE,
-                &sol_feed_id,
-            )?; //得到不stale于100s的价格
-            let new_value = calculate_collateral_interest(
-                user.deposited_sol,
-                bank.interest_rate,
-                user.last_updated,
-            )?;
-            total_collateral_in_usd = sol_price.price as u64 * new_value;
-            amount_in_usd = amount * sol_price.price as u64;
-        }
-        _ => {
-            let usdc_feed_id = get_feed_id_from_hex(USDC_USD_FEED_ID)?;
-            let usdc_price = sol_or_usdc_price_feed.get_price_no_older_than(
-                &Clock::get()?,
-                MAX_AGE,
-                &usdc_feed_id,
-            )?;
-            let new_value = calculate_collateral_interest(
-                user.deposited_usdc,
-                bank.interest_rate,
-                user.last_updated,
-            )?;
-            total_collateral_in_usd = usdc_price.price as u64 * new_value;
-            amount_in_usd = amount * usdc_price.price as u64;
-        }
-    }
-
-    msg!("total_collateral_in_usd: {}", total_collateral_in_usd);
-    msg!("bank.max_ltv: {}", bank.max_ltv);
-    // 通过抵押物的价值计算出用户可以借出的最大金额(usd为单位)
-    let borrowable_amount_in_usd = total_collateral_in_usd
-        .checked_div(10_000)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_mul(bank.max_ltv)
-        .ok_or(ErrorCode::MathOverflow)?;
+    bank.total_borrowed_shares += user_shares;
+    bank.total_borrowed += amount;
+    bank.check_borrow_cap(&bank_key, ctx.remaining_accounts)?;
 
+    let user = &mut ctx.accounts.user;
+    // 使用e-mode时只能借出同一类别中的资产
     require!(
-        borrowable_amount_in_usd >= amount_in_usd,
-        ErrorCode::OverBorrowableAmount
+        user.emode_category == 0 || user.emode_category == bank.emode_category,
+        ErrorCode::EmodeCategoryMismatch
     );
+    let position = user.get_or_create_position(&bank_key)?;
+    position.borrow_shares += user_shares;
+    position.borrow_index = bank.borrow_index;
+
+    // 隔离模式下只能借出borrowable_in_isolation的资产, 并计入隔离资产的总借款
+    if let Some(isolated) = user.isolated_collateral() {
+        require!(
+            bank.borrowable_in_isolation && isolated != bank_key,
+            ErrorCode::IsolationBorrowNotAllowed
+        );
+        let debt = nominal_usd_value(amount, bank.mint_decimals)?;
+        ctx.accounts
+            .isolated_bank
+            .as_mut()
+            .filter(|isolated_bank| isolated_bank.key() == isolated)
+            .ok_or(ErrorCode::InvalidIsolatedBank)?
+            .add_isolated_debt(debt)?;
+    }
+
+    // 加上本次借款之后, 所有借款的价值不能超过按max_ltv加权的抵押物价值
+    let health = compute_health(
+        &ctx.accounts.user,
+        ctx.remaining_accounts,
+        &[(bank_key, &ctx.accounts.bank)],
+        ctx.accounts
+            .market
+            .emode_category(ctx.accounts.user.emode_category),
+        true,
+    )?;
+    require!(health.is_within_max_ltv(), ErrorCode::OverBorrowableAmount);
+
+    // 健康度检查中借款按liability_price估值
+    let (_, price_update) = load_bank_and_price(ctx.remaining_accounts, &bank_key)?;
+    let price = get_price(&ctx.accounts.bank, &price_update)?;
 
     let transfer_cpx_account = TransferChecked {
         from: ctx.accounts.bank_token_account.to_account_info(),
@@ -127,32 +147,54 @@ pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
 
     transfer_checked(transfer_ctx, amount, ctx.accounts.mint.decimals)?;
 
-    if bank.total_borrowed == 0 {
-        bank.total_borrowed = amount;
-        bank.total_borrowed_shares = amount;
-    }
-
-    let borrow_ratio = amount.checked_div(bank.total_borrowed).unwrap();
-    let user_shares = bank
-        .total_borrowed_shares
-        .checked_mul(borrow_ratio)
-        .unwrap();
-
-    bank.total_borrowed_shares += user_shares;
-    bank.total_borrowed += amount;
-
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.borrowed_usdc += amount;
-            user.borrowed_usdc_shares += user_shares;
-        }
-        _ => {
-            user.borrowed_sol += amount;
-            user.borrowed_sol_shares += user_shares;
-        }
-    }
-
-    user.last_updated_borrow = Clock::get()?.unix_timestamp;
-
+    let bank = &ctx.accounts.bank;
+    emit!(events::Borrow {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        amount,
+        shares: user_shares,
+        price: price.liability_price()?,
+        price_exponent: price.exponent,
+        borrow_index: bank.borrow_index,
+        total_borrowed: bank.total_borrowed,
+        total_borrowed_shares: bank.total_borrowed_shares,
+    });
     Ok(())
 }
+
+// 借出原生SOL: 借出的wSOL经过临时账户解包成lamports返还给用户, 用户原有的wSOL不受影响
+pub fn process_borrow_native(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+    check_native_mint(&ctx.accounts.mint.key())?;
+    let payer = ctx.accounts.payer.to_account_info();
+    let user_token_account = ctx.accounts.user_token_account.to_account_info();
+    let native_token_account = ctx
+        .accounts
+        .native_token_account
+        .as_ref()
+        .ok_or(ErrorCode::MissingNativeTokenAccount)?
+        .to_account_info();
+    let native_token_account_bump = ctx
+        .bumps
+        .native_token_account
+        .ok_or(ErrorCode::MissingNativeTokenAccount)?;
+    let mint = ctx.accounts.mint.to_account_info();
+    let system_program = ctx.accounts.system_program.to_account_info();
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let balance_before = ctx.accounts.user_token_account.amount;
+
+    process_borrow(ctx, amount)?;
+    let received = token_balance(&user_token_account)?
+        .checked_sub(balance_before)
+        .ok_or(ErrorCode::MathOverflow)?;
+    unwrap_sol(
+        payer.clone(),
+        user_token_account.clone(),
+        native_token_account,
+        mint,
+        system_program,
+        token_program.clone(),
+        native_token_account_bump,
+        received,
+    )?;
+    close_if_empty(payer, user_token_account, token_program)
+}
@@ -4,15 +4,37 @@ use anchor_spl::{
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
+use crate::error::ErrorCode;
+use crate::utils::{
+    check_native_mint, close_if_empty, mint_receipt_to_vault, received_amount, wrap_sol,
+};
 use crate::*;
 
 #[derive(Accounts)]
 pub struct Deposit<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [b"receipt", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [b"receipt_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds = [mint.key().as_ref()],
         bump,
+        has_one = market,
     )]
     pub bank: Account<'info, Bank>,
     #[account(
@@ -28,7 +50,8 @@ pub struct Deposit<'info> {
     )]
     pub user: Account<'info, User>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = payer,
         associated_token::mint = mint,
         associated_token::authority = payer,
         associated_token::token_program = token_program,
@@ -41,7 +64,16 @@ pub struct Deposit<'info> {
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+// bank设置了美元存款上限时, remaining_accounts 需要传入该bank的 [bank, price_update]
 pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_DEPOSIT)?;
+    require!(!ctx.accounts.bank.insolvent, ErrorCode::BankInsolvent);
+    ctx.accounts
+        .bank
+        .accrue_interest(Clock::get()?.unix_timestamp)?;
+
     let transfer_cpi_account = TransferChecked {
         from: ctx.accounts.user_token_account.to_account_info(),
         mint: ctx.accounts.mint.to_account_info(),
@@ -52,41 +84,64 @@ pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
     let cpi_ctx = CpiContext::new(cpi_program, transfer_cpi_account);
 
     let decimals = ctx.accounts.mint.decimals;
+    let balance_before = ctx.accounts.bank_token_account.amount;
     transfer_checked(cpi_ctx, amount, decimals)?;
 
+    // 按实际到账的数量记账
+    let amount = received_amount(&mut ctx.accounts.bank_token_account, balance_before)?;
+
+    let bank_key = ctx.accounts.bank.key();
     let bank = &mut ctx.accounts.bank;
 
-    let user_shares = if bank.total_depoists == 0 {
-        // 首次存款，shares 等于存款金额
-        bank.total_depoists = amount;
-        bank.total_deposit_shares = amount;
-        amount
-    } else {
-        // 计算新增份额：(amount * total_shares) / total_deposits
-        amount
-            .checked_mul(bank.total_deposit_shares)
-            .unwrap()
-            .checked_div(bank.total_depoists)
-            .unwrap()
-    };
+    // 份额 = amount / supply_index
+    let user_shares = bank.deposit_shares_for(amount)?;
 
     bank.total_depoists = bank.total_depoists.checked_add(amount).unwrap();
     bank.total_deposit_shares = bank.total_deposit_shares.checked_add(user_shares).unwrap();
+    bank.check_supply_cap(&bank_key, ctx.remaining_accounts)?;
 
-    let user = &mut ctx.accounts.user;
-
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.deposited_usdc += amount;
-            user.deposited_usdc_shares += user_shares;
-        }
-        _ => {
-            user.deposited_sol += amount;
-            user.deposited_sol_shares += user_shares;
-        }
-    }
+    mint_receipt_to_vault(
+        ctx.accounts.receipt_mint.to_account_info(),
+        ctx.accounts.receipt_vault.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        &ctx.accounts.mint.key(),
+        ctx.bumps.receipt_mint,
+        user_shares,
+    )?;
 
+    let position = ctx
+        .accounts
+        .user
+        .get_or_create_deposit_position(&bank_key, bank.asset_tier)?;
+    position.deposit_shares = position.deposit_shares.checked_add(user_shares).unwrap();
+    position.deposit_index = bank.supply_index;
 
-    user.last_updated = Clock::get()?.unix_timestamp;
+    emit!(events::Deposit {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        amount,
+        shares: user_shares,
+        supply_index: bank.supply_index,
+        total_deposits: bank.total_depoists,
+        total_deposit_shares: bank.total_deposit_shares,
+    });
     Ok(())
 }
+
+// 存入原生SOL: 先将lamports包装成wSOL再存入, 用户的wSOL账户为空时关闭, 原有的wSOL不受影响
+pub fn process_deposit_native(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+    check_native_mint(&ctx.accounts.mint.key())?;
+    let payer = ctx.accounts.payer.to_account_info();
+    let user_token_account = ctx.accounts.user_token_account.to_account_info();
+    let token_program = ctx.accounts.token_program.to_account_info();
+    wrap_sol(
+        payer.clone(),
+        user_token_account.clone(),
+        ctx.accounts.system_program.to_account_info(),
+        token_program.clone(),
+        amount,
+    )?;
+
+    process_deposit(ctx, amount)?;
+    close_if_empty(payer, user_token_account, token_program)
+}
@@ -0,0 +1,170 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::Discriminator;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::constants::*;
+use crate::error::ErrorCode;
+use crate::events::FlashLoan as FlashLoanEvent;
+use crate::state::*;
+use crate::utils::{mul_div_up, received_amount, to_u64};
+
+// flash_borrow 和 flash_repay 使用相同的账户, bank在账户列表中的位置用于匹配同一笔交易中的flash_repay
+const FLASH_LOAN_BANK_INDEX: usize = 1;
+
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        mut,
+        seeds = [b"treasury",mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: 通过地址约束确认是instructions sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+// 无抵押地从treasury借出amount, 同一笔交易中之后必须有同一个bank的flash_repay
+pub fn process_flash_borrow(ctx: Context<FlashLoan>, amount: u64) -> Result<()> {
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_FLASH_LOAN)?;
+    require!(
+        ctx.accounts.bank.flash_loan_amount == 0,
+        ErrorCode::FlashLoanInProgress
+    );
+
+    let instructions = ctx.accounts.instructions.to_account_info();
+    let current_index = load_current_index_checked(&instructions)? as usize;
+    // 不允许通过CPI调用, 否则current_index指向的是外层程序的指令
+    let current = load_instruction_at_checked(current_index, &instructions)?;
+    require_keys_eq!(
+        current.program_id,
+        crate::ID,
+        ErrorCode::FlashLoanCpiNotAllowed
+    );
+
+    let bank_key = ctx.accounts.bank.key();
+    let mut index = current_index + 1;
+    let mut repay_found = false;
+    while let Ok(ix) = load_instruction_at_checked(index, &instructions) {
+        if ix.program_id == crate::ID
+            && ix.data.get(..8) == Some(&crate::instruction::FlashRepay::DISCRIMINATOR[..])
+            && ix
+                .accounts
+                .get(FLASH_LOAN_BANK_INDEX)
+                .is_some_and(|meta| meta.pubkey == bank_key)
+        {
+            repay_found = true;
+            break;
+        }
+        index += 1;
+    }
+    require!(repay_found, ErrorCode::FlashRepayNotFound);
+
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    bank.flash_loan_amount = amount;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        transfer_cpi_accounts,
+        signer_seeds,
+    );
+    transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+    Ok(())
+}
+
+// 归还flash_borrow借出的数量加上手续费, 手续费按reserve_factor分给协议和存款人
+pub fn process_flash_repay(ctx: Context<FlashLoan>) -> Result<()> {
+    let amount = ctx.accounts.bank.flash_loan_amount;
+    require!(amount > 0, ErrorCode::FlashLoanNotActive);
+
+    let fee = to_u64(mul_div_up(
+        amount as u128,
+        ctx.accounts.bank.flash_loan_fee as u128,
+        10_000,
+    )?)?;
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        authority: ctx.accounts.payer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        transfer_cpi_accounts,
+    );
+    let balance_before = ctx.accounts.bank_token_account.amount;
+    transfer_checked(
+        cpi_ctx,
+        amount.checked_add(fee).ok_or(ErrorCode::MathOverflow)?,
+        ctx.accounts.mint.decimals,
+    )?;
+
+    // 借出的本金必须全部归还, 只把实际多收到的部分作为手续费分配
+    let received = received_amount(&mut ctx.accounts.bank_token_account, balance_before)?;
+    require!(received >= amount, ErrorCode::InsufficientFunds);
+    let fee = received - amount;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.flash_loan_amount = 0;
+    bank.distribute_fee(fee)?;
+
+    emit!(FlashLoanEvent {
+        bank: bank.key(),
+        user: ctx.accounts.payer.key(),
+        amount,
+        fee,
+        supply_index: bank.supply_index,
+        total_reserves: bank.total_reserves,
+    });
+    Ok(())
+}
@@ -1,25 +1,38 @@
 use crate::constants::*;
 use crate::error::ErrorCode;
+use crate::events;
+use crate::health::{compute_health, nominal_usd_value, token_amount, usd_value};
+use crate::oracle::get_price;
 use crate::state::*;
-use crate::utils::calculate_collateral_interest;
+use crate::utils::{
+    burn_receipt_from_vault, load_bank_and_price, mul_div_down, received_amount, to_u64,
+};
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
 #[derive(Accounts)]
 pub struct Liquidate<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Box<Account<'info, LendingMarket>>,
     #[account(
         mut,
         seeds = [collateral_mint.key().as_ref()],
-        bump
+        bump,
+        has_one = market,
     )]
     pub collateral_bank: Box<Account<'info, Bank>>,
     #[account(
         mut,
         seeds = [borrowed_mint.key().as_ref()],
-        bump
+        bump,
+        has_one = market,
+        // 两个bank是同一个账户时, 后写回的borrowed_bank会覆盖collateral_bank的修改
+        constraint = collateral_bank.key() != borrowed_bank.key() @ ErrorCode::SameBankLiquidation,
     )]
     pub borrowed_bank: Box<Account<'info, Bank>>,
     #[account(
@@ -50,16 +63,27 @@ pub struct Liquidate<'info> {
         associated_token::token_program = token_program,
     )]
     pub liquidator_borrowed_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    // 被清算的借款人账户, 任何人都可以清算健康度不足的借款人
     #[account(
         mut,
-        seeds = [liquidator.key().as_ref()],
+        seeds = [user.owner.as_ref()],
         bump
     )]
     pub user: Box<Account<'info, User>>,
+    #[account(
+        mut,
+        seeds = [b"receipt", collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [b"receipt_vault", collateral_mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     pub collateral_mint: InterfaceAccount<'info, Mint>,
     pub borrowed_mint: InterfaceAccount<'info, Mint>,
-    pub sol_price_feed: Account<'info, PriceUpdateV2>,
-    pub usdc_price_feed: Account<'info, PriceUpdateV2>,
     #[account(mut)]
     pub liquidator: Signer<'info>,
     pub token_program: Interface<'info, TokenInterface>,
@@ -67,98 +91,132 @@ pub struct Liquidate<'info> {
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
-pub fn process_liquidate(ctx: Context<Liquidate>) -> Result<()> {
+// repay_amount: 清算人愿意偿还的借款数量, 超过close factor允许的部分会被截断
+// min_collateral_out: 清算人至少需要获得的抵押物数量, 防止价格变动造成的滑点
+// remaining_accounts: 为被清算用户的每个position按 [bank, price_update] 成对传入
+pub fn process_liquidate(
+    ctx: Context<Liquidate>,
+    repay_amount: u64,
+    min_collateral_out: u64,
+) -> Result<()> {
+    // 清算的两个bank都不能暂停清算
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.collateral_bank, PAUSE_LIQUIDATE)?;
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.borrowed_bank, PAUSE_LIQUIDATE)?;
+    let now = Clock::get()?.unix_timestamp;
     let collateral_bank = &mut ctx.accounts.collateral_bank;
+    collateral_bank.accrue_interest(now)?;
     let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+    borrowed_bank.accrue_interest(now)?;
+    let collateral_bank_key = collateral_bank.key();
+    let borrowed_bank_key = borrowed_bank.key();
 
-    let user = &mut ctx.accounts.user;
+    //通过同一单位usd 汇总用户所有的存款和借款来计算 健康因子
+    let health = compute_health(
+        &ctx.accounts.user,
+        ctx.remaining_accounts,
+        &[
+            (collateral_bank_key, &ctx.accounts.collateral_bank),
+            (borrowed_bank_key, &ctx.accounts.borrowed_bank),
+        ],
+        ctx.accounts
+            .market
+            .emode_category(ctx.accounts.user.emode_category),
+        false,
+    )?;
 
-    let sol_price_feed = &mut ctx.accounts.sol_price_feed;
-    let usdc_price_feed = &mut ctx.accounts.usdc_price_feed;
+    //如果health_factor >=1,那么就不需要被清算,反之需要被清算
+    require!(health.is_liquidatable(), ErrorCode::NotUnderCollateralized);
 
-    let sol_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID)?;
-    let usdc_feed_id = get_feed_id_from_hex(USDC_USD_FEED_ID)?;
+    let (_, collateral_price_update) =
+        load_bank_and_price(ctx.remaining_accounts, &collateral_bank_key)?;
+    let (_, borrowed_price_update) =
+        load_bank_and_price(ctx.remaining_accounts, &borrowed_bank_key)?;
+    let collateral_price = get_price(&ctx.accounts.collateral_bank, &collateral_price_update)?;
+    let borrowed_price = get_price(&ctx.accounts.borrowed_bank, &borrowed_price_update)?;
 
-    let sol_price =
-        sol_price_feed.get_price_no_older_than(&Clock::get()?, MAX_AGE, &sol_feed_id)?;
-    let usdc_price =
-        usdc_price_feed.get_price_no_older_than(&Clock::get()?, MAX_AGE, &usdc_feed_id)?;
+    let collateral_bank = &mut ctx.accounts.collateral_bank;
+    let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+    let user = &mut ctx.accounts.user;
 
-    //都是以usd为单位
-    let total_collateral: u64;
-    let total_borrowed_in_usd: u64;
+    let collateral_position = user
+        .find_position(&collateral_bank_key)
+        .ok_or(ErrorCode::PositionNotFound)?;
+    // 用户关闭了抵押的存款不能被清算, 除非所有抵押物都已经被清算, 否则借款无法收回
+    require!(
+        collateral_position.collateral_enabled || !user.has_collateral(),
+        ErrorCode::CollateralDisabled
+    );
+    let collateral_shares_owned = collateral_position.deposit_shares;
+    let is_isolated_collateral = user.isolated_collateral() == Some(collateral_bank_key);
+    let borrow_shares_owned = user
+        .find_position(&borrowed_bank_key)
+        .ok_or(ErrorCode::PositionNotFound)?
+        .borrow_shares;
 
     //总共借款的清算金额= 借款 + 利息
-    let borrow_liquidation_amount: u64;
-
-    match ctx.accounts.collateral_mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            let new_usdc = calculate_collateral_interest(
-                user.deposited_usdc,
-                collateral_bank.interest_rate,
-                user.last_updated,
-            )?;
-            total_collateral = new_usdc
-                .checked_mul(usdc_price.price as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(10u64.pow(PRICE_DECIMALS as u32))
-                .ok_or(ErrorCode::MathOverflow)?;
-            //借款的金额
-            borrow_liquidation_amount = calculate_collateral_interest(
-                user.borrowed_sol,
-                collateral_bank.interest_rate,
-                user.last_updated_borrow,
-            )?;
-            msg!("borrow_liquidation_amount: {}", borrow_liquidation_amount);
-            total_borrowed_in_usd = borrow_liquidation_amount
-                .checked_mul(sol_price.price as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(10u64.pow(PRICE_DECIMALS as u32))
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-        _ => {
-            let new_sol = calculate_collateral_interest(
-                user.deposited_sol,
-                collateral_bank.interest_rate,
-                user.last_updated,
-            )?;
-            total_collateral = new_sol
-                .checked_mul(sol_price.price as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(10u64.pow(PRICE_DECIMALS as u32))
-                .ok_or(ErrorCode::MathOverflow)?;
-            borrow_liquidation_amount = calculate_collateral_interest(
-                user.borrowed_usdc,
-                collateral_bank.interest_rate,
-                user.last_updated_borrow,
-            )?;
-            total_borrowed_in_usd = borrow_liquidation_amount
-                .checked_mul(usdc_price.price as u64)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(10u64.pow(PRICE_DECIMALS as u32))
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-    }
+    let borrow_liquidation_amount = borrowed_bank.borrow_amount(borrow_shares_owned)?;
 
-    //通过同一单位usd 来计算 健康因子
-    msg!("数据溢出位置1");
-    msg!("total_collateral_in_usd: {}", total_collateral);
-    msg!("total_borrowed_in_usd: {}", total_borrowed_in_usd);
-    msg!("sol_price: {}", sol_price.price);
-    msg!("usdc_price: {}", usdc_price.price);
-    msg!("liquidation_threshold: {}", collateral_bank.liquidation_threshold);
-    let health_factor = total_collateral
-        .checked_div(10_000 as u64)
+    //计算借款的清算金额, 单次最多偿还 借款 * liquidation_close_factor
+    let mut borrow_liquidation_amount_liquidation_close_factor = borrow_liquidation_amount
+        .checked_mul(borrowed_bank.liquidation_close_factor)
         .ok_or(ErrorCode::MathOverflow)?
-        .checked_mul(collateral_bank.liquidation_threshold)
+        .checked_div(10_000)
         .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(total_borrowed_in_usd)
-        .ok_or(ErrorCode::MathOverflow)?;
+        .min(repay_amount);
 
-    //如果health_factor >=1,那么就不需要被清算,反之需要被清算
-    require!(health_factor < 1_u64, ErrorCode::NotUnderCollateralized);
+    //清算奖励随健康因子低于1的程度增加, 抵押物属于用户的e-mode类别时使用类别的清算奖励
+    let health_factor = health.health_factor()?;
+    let liquidation_bonus = match ctx
+        .accounts
+        .market
+        .emode_category(user.emode_category)
+        .filter(|category| category.id == collateral_bank.emode_category)
+    {
+        Some(category) => category.liquidation_bonus,
+        None => collateral_bank.liquidation_bonus_for(health_factor),
+    };
+
+    //将borrow_liquidation_amount_liquidation_close_factor 按现货价格换算成usd
+    //再加上清算奖励换算成liquidator可以获得的质押代币数量
+    let repaid_value = usd_value(
+        borrow_liquidation_amount_liquidation_close_factor,
+        borrowed_price.spot(),
+        borrowed_price.exponent,
+        borrowed_bank.mint_decimals,
+    )?;
+    let seized_value = mul_div_down(repaid_value, 10_000 + liquidation_bonus as u128, 10_000)?;
+    let mut collateral_liquidation_amount = token_amount(
+        seized_value,
+        collateral_price.spot(),
+        collateral_price.exponent,
+        collateral_bank.mint_decimals,
+    )?;
+
+    // 不能超过用户在collateral bank中的存款, 剩余抵押物不足以支付奖励时按比例减少偿还的借款
+    let collateral_deposited = collateral_bank.deposit_amount(collateral_shares_owned)?;
+    if collateral_liquidation_amount > collateral_deposited {
+        collateral_liquidation_amount = collateral_deposited;
+        let seized_value = usd_value(
+            collateral_deposited,
+            collateral_price.spot(),
+            collateral_price.exponent,
+            collateral_bank.mint_decimals,
+        )?;
+        let repaid_value = mul_div_down(seized_value, 10_000, 10_000 + liquidation_bonus as u128)?;
+        borrow_liquidation_amount_liquidation_close_factor = token_amount(
+            repaid_value,
+            borrowed_price.spot(),
+            borrowed_price.exponent,
+            borrowed_bank.mint_decimals,
+        )?
+        .min(borrow_liquidation_amount_liquidation_close_factor);
+    }
 
-    //当需要清算的时候,首先被清算的用户需要将借的钱根据liquidation_close_factor归还
+    //liquidator 先替被清算的用户归还借款
     let transfer_to_bank = TransferChecked {
         from: ctx
             .accounts
@@ -169,76 +227,55 @@ pub fn process_liquidate(ctx: Context<Liquidate>) -> Result<()> {
         mint: ctx.accounts.borrowed_mint.to_account_info(),
     };
     let transfer_to_bank_cpi_ctx = CpiContext::new(
-        ctx.accounts.system_program.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
         transfer_to_bank,
     );
-
-    msg!("数据溢出位置2");
-    //计算借款的清算金额
-    let borrow_liquidation_amount_liquidation_close_factor = borrow_liquidation_amount
-        .checked_mul(borrowed_bank.liquidation_close_factor)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(10_000)
-        .ok_or(ErrorCode::MathOverflow)?;
-
+    let balance_before = ctx.accounts.borrowed_bank_token_account.amount;
     transfer_checked(
         transfer_to_bank_cpi_ctx,
         borrow_liquidation_amount_liquidation_close_factor,
         ctx.accounts.borrowed_mint.decimals,
     )?;
 
-    msg!("数据溢出位置3");
+    // 按实际到账的数量偿还借款, 获得的抵押物也按比例减少
+    let received = received_amount(
+        &mut ctx.accounts.borrowed_bank_token_account,
+        balance_before,
+    )?;
+    if received < borrow_liquidation_amount_liquidation_close_factor {
+        collateral_liquidation_amount = to_u64(mul_div_down(
+            collateral_liquidation_amount as u128,
+            received as u128,
+            borrow_liquidation_amount_liquidation_close_factor as u128,
+        )?)?;
+        borrow_liquidation_amount_liquidation_close_factor = received;
+    }
+    require!(
+        collateral_liquidation_amount >= min_collateral_out,
+        ErrorCode::LiquidationSlippageExceeded
+    );
+
     //计算借款的份额 并 更新信息
-    let borrow_shares = borrow_liquidation_amount_liquidation_close_factor
-        .checked_mul(borrowed_bank.total_borrowed_shares)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(borrowed_bank.total_borrowed)
-        .ok_or(ErrorCode::MathOverflow)?;
+    let borrow_shares =
+        borrowed_bank.repay_shares_for(borrow_liquidation_amount_liquidation_close_factor)?;
 
     borrowed_bank.total_borrowed_shares -= borrow_shares;
-    borrowed_bank.total_borrowed -= borrow_liquidation_amount_liquidation_close_factor;
+    borrowed_bank.total_borrowed = borrowed_bank
+        .total_borrowed
+        .saturating_sub(borrow_liquidation_amount_liquidation_close_factor);
 
-    //将borrow_liquidation_amount_liquidation_close_factor 换算成usd
-    //再将usd 换算成 collateral_liquidation_amount_liquidation_close_factor
-    //同时 更新 user的借款信息
-    msg!("数据溢出位置4");
-    let collateral_liquidation_amount_liquidation_close_factor: u64;
-    match ctx.accounts.collateral_mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.borrowed_sol -= borrow_liquidation_amount_liquidation_close_factor;
-            user.borrowed_sol_shares -= borrow_shares;
+    let position = user.find_position_mut(&borrowed_bank_key)?;
+    position.borrow_shares -= borrow_shares;
+    position.borrow_index = borrowed_bank.borrow_index;
 
-            collateral_liquidation_amount_liquidation_close_factor =
-                borrow_liquidation_amount_liquidation_close_factor
-                    .checked_mul(sol_price.price as u64)
-                    .ok_or(ErrorCode::MathOverflow)?
-                    .checked_div(usdc_price.price as u64)
-                    .ok_or(ErrorCode::MathOverflow)?;
-        }
-        _ => {
-            user.borrowed_usdc -= borrow_liquidation_amount_liquidation_close_factor;
-            user.borrowed_usdc_shares -= borrow_shares;
-
-            collateral_liquidation_amount_liquidation_close_factor =
-                borrow_liquidation_amount_liquidation_close_factor
-                    .checked_mul(usdc_price.price as u64)
-                    .ok_or(ErrorCode::MathOverflow)?
-                    .checked_div(sol_price.price as u64)
-                    .ok_or(ErrorCode::MathOverflow)?;
-        }
+    // 被清算的用户处于隔离模式时, 偿还的借款同时从隔离资产的总借款中扣除
+    if is_isolated_collateral {
+        collateral_bank.reduce_isolated_debt(nominal_usd_value(
+            borrow_liquidation_amount_liquidation_close_factor,
+            borrowed_bank.mint_decimals,
+        )?);
     }
 
-    msg!("数据溢出位置5");
-    //collateral_liquidation_amount 是liquidator可以获得的质押代币数量:归还的钱加上清算奖金
-    //即 collateral_liquidation_amount_liquidation_close_factor  + bonus
-    let collateral_liquidation_amount = collateral_liquidation_amount_liquidation_close_factor
-        .checked_mul(collateral_bank.liquidation_bonus)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(10_000)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_add(collateral_liquidation_amount_liquidation_close_factor)
-        .ok_or(ErrorCode::MathOverflow)?;
-
     let transfer_to_liquidator = TransferChecked {
         from: ctx.accounts.collateral_bank_token_account.to_account_info(),
         to: ctx
@@ -268,26 +305,49 @@ pub fn process_liquidate(ctx: Context<Liquidate>) -> Result<()> {
         ctx.accounts.collateral_mint.decimals,
     )?;
 
-    msg!("数据溢出位置6");
-    let collateral_shares = collateral_liquidation_amount
-        .checked_mul(collateral_bank.total_deposit_shares)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(collateral_bank.total_depoists)
-        .ok_or(ErrorCode::MathOverflow)?;
+    let collateral_shares = collateral_bank
+        .withdraw_shares_for(collateral_liquidation_amount)?
+        .min(collateral_shares_owned);
 
     collateral_bank.total_deposit_shares -= collateral_shares;
-    collateral_bank.total_depoists -= collateral_liquidation_amount;
+
+    burn_receipt_from_vault(
+        ctx.accounts.collateral_receipt_mint.to_account_info(),
+        ctx.accounts.collateral_receipt_vault.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        &mint_key,
+        ctx.bumps.collateral_receipt_vault,
+        collateral_shares,
+    )?;
+    collateral_bank.total_depoists = collateral_bank
+        .total_depoists
+        .saturating_sub(collateral_liquidation_amount);
 
     // 更新清算了之后用户的信息
-    match ctx.accounts.collateral_mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.deposited_usdc -= collateral_liquidation_amount;
-            user.deposited_usdc_shares -= collateral_shares;
-        }
-        _ => {
-            user.deposited_sol -= collateral_liquidation_amount;
-            user.deposited_sol_shares -= collateral_shares;
-        }
-    }
+    let position = user.find_position_mut(&collateral_bank_key)?;
+    position.deposit_shares -= collateral_shares;
+    position.deposit_index = collateral_bank.supply_index;
+    user.remove_empty_positions();
+
+    emit!(events::Liquidation {
+        liquidator: ctx.accounts.liquidator.key(),
+        user: user.key(),
+        collateral_bank: collateral_bank_key,
+        borrowed_bank: borrowed_bank_key,
+        repaid_amount: borrow_liquidation_amount_liquidation_close_factor,
+        repaid_shares: borrow_shares,
+        seized_amount: collateral_liquidation_amount,
+        seized_shares: collateral_shares,
+        collateral_price: collateral_price.spot(),
+        collateral_price_exponent: collateral_price.exponent,
+        borrowed_price: borrowed_price.spot(),
+        borrowed_price_exponent: borrowed_price.exponent,
+        health_factor,
+        liquidation_bonus,
+        collateral_total_deposits: collateral_bank.total_depoists,
+        collateral_total_deposit_shares: collateral_bank.total_deposit_shares,
+        borrowed_total_borrowed: borrowed_bank.total_borrowed,
+        borrowed_total_borrowed_shares: borrowed_bank.total_borrowed_shares,
+    });
     Ok(())
 }
@@ -1,6 +1,9 @@
 pub mod admin;
 pub use admin::*;
 
+pub mod refresh_bank;
+pub use refresh_bank::*;
+
 pub mod deposit;
 pub use deposit::*;
 
@@ -15,3 +18,18 @@ pub use repay::*;
 
 pub mod liquidate;
 pub use liquidate::*;
+
+pub mod set_emode;
+pub use set_emode::*;
+
+pub mod set_collateral;
+pub use set_collateral::*;
+
+pub mod receipt;
+pub use receipt::*;
+
+pub mod flash_loan;
+pub use flash_loan::*;
+
+pub mod writeoff_bad_debt;
+pub use writeoff_bad_debt::*;
@@ -0,0 +1,164 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+use crate::constants::*;
+use crate::error::ErrorCode;
+use crate::events;
+use crate::health::compute_health;
+use crate::state::*;
+
+#[derive(Accounts)]
+pub struct ReceiptTransfer<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    #[account(
+        seeds = [b"receipt", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [b"receipt_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [payer.key().as_ref()],
+        bump,
+    )]
+    pub user: Account<'info, User>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = payer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_receipt_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+// 将position中的shares份存款转成用户钱包中的receipt token, 转出的部分不再作为抵押物
+// remaining_accounts: 用户有借款时, 为用户的每个position按 [bank, price_update] 成对传入
+pub fn process_mint_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_WITHDRAW)?;
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    let bank_key = bank.key();
+
+    let user = &mut ctx.accounts.user;
+    let position = user.find_position_mut(&bank_key)?;
+    let check_health = position.collateral_enabled;
+    require!(
+        position.deposit_shares >= shares,
+        ErrorCode::InsufficientFunds
+    );
+    position.deposit_shares -= shares;
+    position.deposit_index = bank.supply_index;
+    user.remove_empty_positions();
+
+    // 转出之后剩余的抵押物仍然需要覆盖所有借款
+    if check_health && ctx.accounts.user.has_borrows() {
+        let health = compute_health(
+            &ctx.accounts.user,
+            ctx.remaining_accounts,
+            &[(bank_key, &ctx.accounts.bank)],
+            ctx.accounts
+                .market
+                .emode_category(ctx.accounts.user.emode_category),
+            true,
+        )?;
+        require!(health.is_within_max_ltv(), ErrorCode::WithdrawExceedsMaxLtv);
+    }
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.receipt_vault.to_account_info(),
+        to: ctx.accounts.user_receipt_account.to_account_info(),
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        authority: ctx.accounts.receipt_vault.to_account_info(),
+    };
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"receipt_vault",
+        mint_key.as_ref(),
+        &[ctx.bumps.receipt_vault],
+    ]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        transfer_cpi_accounts,
+        signer_seeds,
+    );
+    transfer_checked(cpi_ctx, shares, ctx.accounts.receipt_mint.decimals)?;
+
+    emit!(events::ReceiptMinted {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        shares,
+        supply_index: ctx.accounts.bank.supply_index,
+    });
+    Ok(())
+}
+
+// 将钱包中的receipt token存回position, 重新作为存款份额计入并可以用作抵押物
+pub fn process_redeem_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_DEPOSIT)?;
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    let bank_key = bank.key();
+
+    let transfer_cpi_accounts = TransferChecked {
+        from: ctx.accounts.user_receipt_account.to_account_info(),
+        to: ctx.accounts.receipt_vault.to_account_info(),
+        mint: ctx.accounts.receipt_mint.to_account_info(),
+        authority: ctx.accounts.payer.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        transfer_cpi_accounts,
+    );
+    transfer_checked(cpi_ctx, shares, ctx.accounts.receipt_mint.decimals)?;
+
+    let supply_index = ctx.accounts.bank.supply_index;
+    let position = ctx
+        .accounts
+        .user
+        .get_or_create_deposit_position(&bank_key, ctx.accounts.bank.asset_tier)?;
+    position.deposit_shares = position
+        .deposit_shares
+        .checked_add(shares)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.deposit_index = supply_index;
+
+    emit!(events::ReceiptRedeemed {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        shares,
+        supply_index,
+    });
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+use crate::events::BankRefreshed;
+use crate::state::*;
+
+// 任何人都可以调用, 将bank的利率指数和总存款/总借款更新到当前时间
+#[derive(Accounts)]
+pub struct RefreshBank<'info> {
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub mint: InterfaceAccount<'info, Mint>,
+}
+
+pub fn process_refresh_bank(ctx: Context<RefreshBank>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+    emit!(BankRefreshed {
+        bank: bank.key(),
+        supply_index: bank.supply_index,
+        borrow_index: bank.borrow_index,
+        total_deposits: bank.total_depoists,
+        total_borrowed: bank.total_borrowed,
+        total_reserves: bank.total_reserves,
+    });
+    Ok(())
+}
@@ -1,22 +1,36 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
+use crate::constants::*;
 use crate::error::ErrorCode;
+use crate::events;
+use crate::health::nominal_usd_value;
 use crate::state::*;
+use crate::utils::{check_native_mint, close_if_empty, received_amount, wrap_sol};
 
 #[derive(Accounts)]
 pub struct Repay<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
     #[account(
         mut,
         seeds = [mint.key().as_ref()],
-        bump
+        bump,
+        has_one = market,
     )]
     pub bank: Account<'info, Bank>,
+    // 用户处于隔离模式时需要传入作为抵押物的隔离资产bank, 用于记录以它为抵押的总借款
+    #[account(
+        mut,
+        has_one = market,
+    )]
+    pub isolated_bank: Option<Box<Account<'info, Bank>>>,
     #[account(
         mut,
         seeds = [b"treasury",mint.key().as_ref()],
@@ -30,7 +44,8 @@ pub struct Repay<'info> {
     )]
     pub user: Account<'info, User>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = payer,
         associated_token::mint = mint,
         associated_token::authority = payer,
         associated_token::token_program = token_program
@@ -45,30 +60,22 @@ pub struct Repay<'info> {
 }
 
 pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user;
-
-    let borrow_value: u64;
-
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            borrow_value = user.borrowed_usdc;
-        }
-        _ => {
-            borrow_value = user.borrowed_sol;
-        }
-    }
-
-    let time_diff = user.last_updated_borrow - Clock::get()?.unix_timestamp;
-
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_REPAY)?;
     let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
 
-    // 计算利息
-    bank.total_borrowed =
-        (bank.total_borrowed as f64 * E.powf(bank.interest_rate as f64 * time_diff as f64)) as u64;
-
-    let value_per_share = bank.total_borrowed as f64 / bank.total_borrowed_shares as f64;
+    let bank_key = bank.key();
+    let borrow_shares = ctx
+        .accounts
+        .user
+        .find_position(&bank_key)
+        .ok_or(ErrorCode::PositionNotFound)?
+        .borrow_shares;
 
-    let user_value = borrow_value.checked_mul(value_per_share as u64).unwrap();
+    // 借款 + 利息
+    let user_value = bank.borrow_amount(borrow_shares)?;
 
     require!(user_value >= amount, ErrorCode::OverRepay);
 
@@ -84,27 +91,66 @@ pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
         transfer_cpi_account,
     );
 
+    let balance_before = ctx.accounts.bank_token_account.amount;
     transfer_checked(transfer_cpi_ctx, amount, ctx.accounts.mint.decimals)?;
 
-    let borrow_ratio = amount.checked_div(bank.total_borrowed).unwrap();
-    let user_shares = bank
-        .total_borrowed_shares
-        .checked_mul(borrow_ratio)
-        .unwrap();
-
-    match ctx.accounts.mint.to_account_info().key() {
-        key if key == user.usdc_address => {
-            user.borrowed_usdc -= amount;
-            user.borrowed_usdc_shares -= user_shares;
-        }
-        _ => {
-            user.borrowed_sol -= amount;
-            user.borrowed_sol_shares -= user_shares;
-        }
+    // 按实际到账的数量记账
+    let amount = received_amount(&mut ctx.accounts.bank_token_account, balance_before)?;
+
+    let bank = &mut ctx.accounts.bank;
+
+    // 全额还款时清空所有份额, 避免留下取整造成的残余债务
+    let user_shares = if amount == user_value {
+        borrow_shares
+    } else {
+        bank.repay_shares_for(amount)?
+    };
+
+    let user = &mut ctx.accounts.user;
+    // 隔离模式下还款同时减少隔离资产的总借款
+    if let Some(isolated) = user.isolated_collateral() {
+        let debt = nominal_usd_value(amount, bank.mint_decimals)?;
+        ctx.accounts
+            .isolated_bank
+            .as_mut()
+            .filter(|isolated_bank| isolated_bank.key() == isolated)
+            .ok_or(ErrorCode::InvalidIsolatedBank)?
+            .reduce_isolated_debt(debt);
     }
+    let position = user.find_position_mut(&bank_key)?;
+    position.borrow_shares -= user_shares;
+    position.borrow_index = bank.borrow_index;
+    user.remove_empty_positions();
 
-    bank.total_borrowed -= amount;
+    bank.total_borrowed = bank.total_borrowed.saturating_sub(amount);
     bank.total_borrowed_shares -= user_shares;
 
+    emit!(events::Repay {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        amount,
+        shares: user_shares,
+        borrow_index: bank.borrow_index,
+        total_borrowed: bank.total_borrowed,
+        total_borrowed_shares: bank.total_borrowed_shares,
+    });
     Ok(())
 }
+
+// 用原生SOL还款: 先将lamports包装成wSOL再还款, 用户的wSOL账户为空时关闭, 原有的wSOL不受影响
+pub fn process_repay_native(ctx: Context<Repay>, amount: u64) -> Result<()> {
+    check_native_mint(&ctx.accounts.mint.key())?;
+    let payer = ctx.accounts.payer.to_account_info();
+    let user_token_account = ctx.accounts.user_token_account.to_account_info();
+    let token_program = ctx.accounts.token_program.to_account_info();
+    wrap_sol(
+        payer.clone(),
+        user_token_account.clone(),
+        ctx.accounts.system_program.to_account_info(),
+        token_program.clone(),
+        amount,
+    )?;
+
+    process_repay(ctx, amount)?;
+    close_if_empty(payer, user_token_account, token_program)
+}
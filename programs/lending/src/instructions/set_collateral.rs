@@ -0,0 +1,67 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+use crate::error::ErrorCode;
+use crate::events::CollateralToggled;
+use crate::health::compute_health;
+use crate::state::*;
+
+#[derive(Accounts)]
+pub struct SetCollateral<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [payer.key().as_ref()],
+        bump,
+    )]
+    pub user: Account<'info, User>,
+    pub payer: Signer<'info>,
+}
+
+// 设置用户在bank中的存款是否作为抵押物
+// remaining_accounts: 关闭抵押且用户有借款时, 为用户的每个position按 [bank, price_update] 成对传入
+pub fn process_set_collateral(ctx: Context<SetCollateral>, enabled: bool) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    let bank_key = bank.key();
+
+    ctx.accounts
+        .user
+        .set_collateral_enabled(&bank_key, bank.asset_tier, enabled)?;
+
+    // 关闭之后剩余的抵押物仍然需要覆盖所有借款
+    if !enabled && ctx.accounts.user.has_borrows() {
+        let health = compute_health(
+            &ctx.accounts.user,
+            ctx.remaining_accounts,
+            &[(bank_key, &ctx.accounts.bank)],
+            ctx.accounts
+                .market
+                .emode_category(ctx.accounts.user.emode_category),
+            true,
+        )?;
+        require!(
+            health.is_within_max_ltv(),
+            ErrorCode::DisableCollateralExceedsMaxLtv
+        );
+    }
+
+    emit!(CollateralToggled {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        enabled,
+    });
+    Ok(())
+}
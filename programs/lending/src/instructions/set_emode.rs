@@ -0,0 +1,71 @@
+use anchor_lang::prelude::*;
+
+use crate::error::ErrorCode;
+use crate::events::UserEmodeUpdated;
+use crate::health::compute_health;
+use crate::state::*;
+use crate::utils::load_bank_and_price;
+
+#[derive(Accounts)]
+pub struct SetUserEmode<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [payer.key().as_ref()],
+        bump,
+    )]
+    pub user: Account<'info, User>,
+    pub payer: Signer<'info>,
+}
+
+// 选择e-mode类别, category为0表示退出e-mode
+// remaining_accounts: 为用户的每个position按 [bank, price_update] 成对传入
+pub fn process_set_user_emode(ctx: Context<SetUserEmode>, category: u8) -> Result<()> {
+    require!(
+        category == 0 || ctx.accounts.market.emode_category(category).is_some(),
+        ErrorCode::InvalidEmodeCategory
+    );
+
+    let user = &mut ctx.accounts.user;
+    let old_category = user.emode_category;
+    user.emode_category = category;
+
+    // 进入类别时已有的借款都必须属于该类别
+    if category != 0 {
+        for position in user.positions.iter() {
+            if position.borrow_shares > 0 {
+                let (bank, _) = load_bank_and_price(ctx.remaining_accounts, &position.bank)?;
+                require!(
+                    bank.emode_category == category,
+                    ErrorCode::EmodeCategoryMismatch
+                );
+            }
+        }
+    }
+
+    // 换成新类别的参数之后, 所有借款仍然需要满足max_ltv
+    if ctx.accounts.user.has_borrows() {
+        let health = compute_health(
+            &ctx.accounts.user,
+            ctx.remaining_accounts,
+            &[],
+            ctx.accounts.market.emode_category(category),
+            true,
+        )?;
+        require!(
+            health.is_within_max_ltv(),
+            ErrorCode::EmodeChangeExceedsMaxLtv
+        );
+    }
+
+    emit!(UserEmodeUpdated {
+        user: ctx.accounts.user.key(),
+        old_category,
+        new_category: category,
+    });
+    Ok(())
+}
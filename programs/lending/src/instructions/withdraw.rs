@@ -1,20 +1,32 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
+use crate::constants::*;
 use crate::error::ErrorCode;
+use crate::events;
+use crate::health::{compute_health, max_withdrawable};
+use crate::oracle::get_price;
 use crate::state::*;
+use crate::utils::{
+    burn_receipt_from_vault, check_native_mint, close_if_empty, load_bank_and_price, token_balance,
+    unwrap_sol,
+};
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
     #[account(
         mut,
         seeds = [mint.key().as_ref()],
         bump,
+        has_one = market,
     )]
     pub bank: Account<'info, Bank>,
     #[account(
@@ -33,10 +45,29 @@ pub struct Withdraw<'info> {
         init_if_needed,
         payer = payer,
         associated_token::mint = mint,
-        associated_token::authority = user,
+        associated_token::authority = payer,
         associated_token::token_program = token_program,
     )]
     pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: 只有原生SOL的版本需要, 通过seeds约束确认地址, 在指令内创建并关闭的临时wSOL账户
+    #[account(
+        mut,
+        seeds = [b"native", payer.key().as_ref()],
+        bump,
+    )]
+    pub native_token_account: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        seeds = [b"receipt", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [b"receipt_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     pub mint: InterfaceAccount<'info, Mint>,
     #[account(mut)]
     pub payer: Signer<'info>,
@@ -45,31 +76,57 @@ pub struct Withdraw<'info> {
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+// amount 为 u64::MAX 时取出当前健康度允许的最大数量
+// remaining_accounts: 用户有借款时, 为用户的每个position按 [bank, price_update] 成对传入
 pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user;
+    ctx.accounts
+        .market
+        .check_not_paused(&ctx.accounts.bank, PAUSE_WITHDRAW)?;
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
 
+    let bank_key = bank.key();
     // 使用deposited_value_shares来用户存储资产的份额
-    let deposited_value_shares: u64;
-
-    if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-        deposited_value_shares = user.deposited_usdc_shares;
+    let position = ctx
+        .accounts
+        .user
+        .find_position(&bank_key)
+        .ok_or(ErrorCode::PositionNotFound)?;
+    let deposited_value_shares = position.deposit_shares;
+    // 不作为抵押物的存款取出时不需要检查健康度
+    let check_health = position.collateral_enabled && ctx.accounts.user.has_borrows();
+
+    let user_value = bank.deposit_amount(deposited_value_shares)?;
+
+    let amount = if amount == u64::MAX {
+        if check_health {
+            let health = compute_health(
+                &ctx.accounts.user,
+                ctx.remaining_accounts,
+                &[(bank_key, &ctx.accounts.bank)],
+                ctx.accounts
+                    .market
+                    .emode_category(ctx.accounts.user.emode_category),
+                true,
+            )?;
+            let (_, price_update) = load_bank_and_price(ctx.remaining_accounts, &bank_key)?;
+            max_withdrawable(
+                &health,
+                &ctx.accounts.bank,
+                ctx.accounts
+                    .market
+                    .emode_category(ctx.accounts.user.emode_category),
+                user_value,
+                &get_price(&ctx.accounts.bank, &price_update)?,
+            )?
+        } else {
+            user_value
+        }
     } else {
-        deposited_value_shares = user.deposited_sol_shares;
-    }
-
-    let time_diff = user.last_updated - Clock::get()?.unix_timestamp;
-
-    let bank = &mut ctx.accounts.bank;
-    //A = P e^(rt) 这里的r和t的单位要一致
-    bank.total_depoists =
-        (bank.total_depoists as f64 * E.powf(bank.interest_rate as f64 * time_diff as f64)) as u64;
-
-    // 计算每个share的价值
-    let value_per_share = bank.total_depoists as f64 / bank.total_deposit_shares as f64;
-
-    let user_value = deposited_value_shares as f64 * value_per_share;
+        amount
+    };
 
-    require!(user_value >= amount as f64, ErrorCode::InsufficientFunds);
+    require!(user_value >= amount, ErrorCode::InsufficientFunds);
 
     let transfer_cpi_accounts = TransferChecked {
         from: ctx.accounts.bank_token_account.to_account_info(),
@@ -92,19 +149,101 @@ pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
 
     let bank = &mut ctx.accounts.bank;
 
-    let shares_to_remove = (amount as f64 / value_per_share) as u64;
+    // 全部取出时清空所有份额, bank资不抵债后价值为0的份额也可以这样移除.
+    // 否则向上取整, 但不能超过用户持有的份额
+    let shares_to_remove = if amount == user_value {
+        deposited_value_shares
+    } else {
+        bank.withdraw_shares_for(amount)?
+            .min(deposited_value_shares)
+    };
 
     let user = &mut ctx.accounts.user;
-
-    if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-        user.deposited_usdc -= amount;
-        user.deposited_usdc_shares -= shares_to_remove as u64;
+    let position = user.find_position_mut(&bank_key)?;
+    position.deposit_shares -= shares_to_remove;
+    position.deposit_index = bank.supply_index;
+    user.remove_empty_positions();
+
+    bank.total_depoists = bank.total_depoists.saturating_sub(amount);
+    bank.total_deposit_shares -= shares_to_remove;
+
+    burn_receipt_from_vault(
+        ctx.accounts.receipt_mint.to_account_info(),
+        ctx.accounts.receipt_vault.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        &mint_key,
+        ctx.bumps.receipt_vault,
+        shares_to_remove,
+    )?;
+
+    // 取款之后剩余的抵押物仍然需要覆盖所有借款
+    let (price, price_exponent) = if check_health {
+        let health = compute_health(
+            &ctx.accounts.user,
+            ctx.remaining_accounts,
+            &[(bank_key, &ctx.accounts.bank)],
+            ctx.accounts
+                .market
+                .emode_category(ctx.accounts.user.emode_category),
+            true,
+        )?;
+        require!(health.is_within_max_ltv(), ErrorCode::WithdrawExceedsMaxLtv);
+
+        let (_, price_update) = load_bank_and_price(ctx.remaining_accounts, &bank_key)?;
+        let price = get_price(&ctx.accounts.bank, &price_update)?;
+        (price.collateral_price()?, price.exponent)
     } else {
-        user.deposited_sol -= amount;
-        user.deposited_sol_shares -= shares_to_remove as u64;
-    }
+        (0, 0)
+    };
 
-    bank.total_depoists -= amount;
-    bank.total_deposit_shares -= shares_to_remove as u64;
+    let bank = &ctx.accounts.bank;
+    emit!(events::Withdraw {
+        bank: bank_key,
+        user: ctx.accounts.user.key(),
+        amount,
+        shares: shares_to_remove,
+        price,
+        price_exponent,
+        supply_index: bank.supply_index,
+        total_deposits: bank.total_depoists,
+        total_deposit_shares: bank.total_deposit_shares,
+    });
     Ok(())
 }
+
+// 取出原生SOL: 取出的wSOL经过临时账户解包成lamports返还给用户, 用户原有的wSOL不受影响
+pub fn process_withdraw_native(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+    check_native_mint(&ctx.accounts.mint.key())?;
+    let payer = ctx.accounts.payer.to_account_info();
+    let user_token_account = ctx.accounts.user_token_account.to_account_info();
+    let native_token_account = ctx
+        .accounts
+        .native_token_account
+        .as_ref()
+        .ok_or(ErrorCode::MissingNativeTokenAccount)?
+        .to_account_info();
+    let native_token_account_bump = ctx
+        .bumps
+        .native_token_account
+        .ok_or(ErrorCode::MissingNativeTokenAccount)?;
+    let mint = ctx.accounts.mint.to_account_info();
+    let system_program = ctx.accounts.system_program.to_account_info();
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let balance_before = ctx.accounts.user_token_account.amount;
+
+    process_withdraw(ctx, amount)?;
+    let received = token_balance(&user_token_account)?
+        .checked_sub(balance_before)
+        .ok_or(ErrorCode::MathOverflow)?;
+    unwrap_sol(
+        payer.clone(),
+        user_token_account.clone(),
+        native_token_account,
+        mint,
+        system_program,
+        token_program.clone(),
+        native_token_account_bump,
+        received,
+    )?;
+    close_if_empty(payer, user_token_account, token_program)
+}
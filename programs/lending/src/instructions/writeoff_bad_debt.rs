@@ -0,0 +1,127 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::error::ErrorCode;
+use crate::events::BadDebtWrittenOff;
+use crate::health::nominal_usd_value;
+use crate::state::*;
+use crate::utils::burn_receipt_from_vault;
+
+// 任何人都可以调用, 当用户在其他bank中已经没有任何存款但仍有借款时, 核销该bank中的剩余借款
+#[derive(Accounts)]
+pub struct WriteoffBadDebt<'info> {
+    #[account(
+        seeds = [b"lending_market"],
+        bump,
+    )]
+    pub market: Account<'info, LendingMarket>,
+    #[account(
+        mut,
+        seeds = [mint.key().as_ref()],
+        bump,
+        has_one = market,
+    )]
+    pub bank: Account<'info, Bank>,
+    // 用户处于隔离模式时需要传入隔离资产bank, 核销的借款同时从它的总借款中扣除
+    #[account(
+        mut,
+        has_one = market,
+    )]
+    pub isolated_bank: Option<Box<Account<'info, Bank>>>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [user.owner.as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+    #[account(
+        mut,
+        seeds = [b"receipt", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [b"receipt_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub receipt_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_writeoff_bad_debt(ctx: Context<WriteoffBadDebt>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+    let bank_key = bank.key();
+    let user = &mut ctx.accounts.user;
+    let isolated = user.isolated_collateral();
+    let position = user.find_position_mut(&bank_key)?;
+    let shares = position.borrow_shares;
+    let debt = bank.borrow_amount(shares)?;
+
+    // 用户在同一个bank中的存款(包括只赚取利息的存款)先抵扣借款, 剩余的借款才由其他存款人承担
+    let deposited = bank.deposit_amount(position.deposit_shares)?;
+    let netted = deposited.min(debt);
+    let netted_shares = if netted == deposited {
+        position.deposit_shares
+    } else {
+        bank.withdraw_shares_for(netted)?
+            .min(position.deposit_shares)
+    };
+    position.deposit_shares -= netted_shares;
+    position.deposit_index = bank.supply_index;
+    position.borrow_shares = 0;
+    position.borrow_index = bank.borrow_index;
+    user.remove_empty_positions();
+
+    // 其他bank中还有存款时借款可以通过清算收回, 不能核销
+    let amount = debt - netted;
+    require!(
+        amount == 0 || !user.has_deposits(),
+        ErrorCode::UserHasCollateral
+    );
+
+    if let Some(isolated) = isolated {
+        ctx.accounts
+            .isolated_bank
+            .as_mut()
+            .filter(|isolated_bank| isolated_bank.key() == isolated)
+            .ok_or(ErrorCode::InvalidIsolatedBank)?
+            .reduce_isolated_debt(nominal_usd_value(debt, bank.mint_decimals)?);
+    }
+
+    bank.total_deposit_shares -= netted_shares;
+    bank.total_depoists = bank.total_depoists.saturating_sub(netted);
+    bank.total_borrowed = bank.total_borrowed.saturating_sub(netted);
+    let mint_key = ctx.accounts.mint.key();
+    burn_receipt_from_vault(
+        ctx.accounts.receipt_mint.to_account_info(),
+        ctx.accounts.receipt_vault.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        &mint_key,
+        ctx.bumps.receipt_vault,
+        netted_shares,
+    )?;
+
+    let old_supply_index = bank.supply_index;
+    let old_reserves = bank.total_reserves;
+    let loss = bank.socialize_loss(shares, amount)?;
+
+    emit!(BadDebtWrittenOff {
+        bank: bank_key,
+        user: user.key(),
+        amount,
+        shares,
+        netted,
+        loss,
+        covered_by_reserves: old_reserves - bank.total_reserves,
+        insolvent: bank.insolvent,
+        old_supply_index,
+        new_supply_index: bank.supply_index,
+        total_deposits: bank.total_depoists,
+        total_borrowed: bank.total_borrowed,
+    });
+    Ok(())
+}
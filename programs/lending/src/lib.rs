@@ -1,6 +1,9 @@
 pub mod constants;
 pub mod error;
+pub mod events;
+pub mod health;
 pub mod instructions;
+pub mod oracle;
 pub mod state;
 pub mod utils;
 use anchor_lang::prelude::*;
@@ -14,12 +17,40 @@ declare_id!("8s8Bqp4G6QmFW6gXn3vCFyWYDwiWixAHUjo4wsr6dftc");
 #[program]
 pub mod lending {
     use super::*;
+    pub fn init_market(ctx: Context<InitMarket>) -> Result<()> {
+        process_init_market(ctx)
+    }
+
+    pub fn set_market_admin(ctx: Context<UpdateMarket>, new_admin: Pubkey) -> Result<()> {
+        process_set_market_admin(ctx, new_admin)
+    }
+
+    pub fn set_guardian(ctx: Context<UpdateMarket>, guardian: Pubkey) -> Result<()> {
+        process_set_guardian(ctx, guardian)
+    }
+
+    pub fn set_emode_category(ctx: Context<UpdateMarket>, category: EmodeCategory) -> Result<()> {
+        process_set_emode_category(ctx, category)
+    }
+
+    pub fn set_market_pause_flags(ctx: Context<SetMarketPauseFlags>, flags: u8) -> Result<()> {
+        process_set_market_pause_flags(ctx, flags)
+    }
+
+    pub fn set_bank_pause_flags(ctx: Context<SetBankPauseFlags>, flags: u8) -> Result<()> {
+        process_set_bank_pause_flags(ctx, flags)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn init_bank(
         ctx: Context<InitBank>,
         liquidation_threshold: u64,
         max_ltv: u64,
         liquidation_close_factor: u64,
         liquidation_bonus: u64,
+        interest_rate_model: InterestRateModel,
+        reserve_factor: u64,
+        oracle: OracleConfig,
     ) -> Result<()> {
         process_init_bank(
             ctx,
@@ -27,30 +58,101 @@ pub mod lending {
             liquidation_close_factor,
             liquidation_bonus,
             max_ltv,
+            interest_rate_model,
+            reserve_factor,
+            oracle,
         )
     }
 
-    pub fn init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
-        process_init_user(ctx, usdc_address)
+    pub fn update_bank_config(ctx: Context<UpdateBankConfig>, args: BankConfigArgs) -> Result<()> {
+        process_update_bank_config(ctx, args)
+    }
+
+    pub fn relist_bank(ctx: Context<UpdateBankConfig>) -> Result<()> {
+        process_relist_bank(ctx)
+    }
+
+    pub fn update_bank_oracle(ctx: Context<UpdateBankOracle>, oracle: OracleConfig) -> Result<()> {
+        process_update_bank_oracle(ctx, oracle)
+    }
+
+    pub fn init_user(ctx: Context<InitUser>) -> Result<()> {
+        process_init_user(ctx)
+    }
+
+    pub fn collect_protocol_fees(ctx: Context<CollectProtocolFees>) -> Result<()> {
+        process_collect_protocol_fees(ctx)
+    }
+
+    pub fn refresh_bank(ctx: Context<RefreshBank>) -> Result<()> {
+        process_refresh_bank(ctx)
     }
 
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
         process_deposit(ctx, amount)
     }
 
+    pub fn deposit_native(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        process_deposit_native(ctx, amount)
+    }
+
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         process_withdraw(ctx, amount)
     }
 
+    pub fn withdraw_native(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        process_withdraw_native(ctx, amount)
+    }
+
     pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
         process_borrow(ctx, amount)
     }
 
+    pub fn borrow_native(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+        process_borrow_native(ctx, amount)
+    }
+
     pub fn repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
         process_repay(ctx, amount)
     }
 
-    pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
-        process_liquidate(ctx)
+    pub fn repay_native(ctx: Context<Repay>, amount: u64) -> Result<()> {
+        process_repay_native(ctx, amount)
+    }
+
+    pub fn liquidate(
+        ctx: Context<Liquidate>,
+        repay_amount: u64,
+        min_collateral_out: u64,
+    ) -> Result<()> {
+        process_liquidate(ctx, repay_amount, min_collateral_out)
+    }
+
+    pub fn set_user_emode(ctx: Context<SetUserEmode>, category: u8) -> Result<()> {
+        process_set_user_emode(ctx, category)
+    }
+
+    pub fn set_collateral(ctx: Context<SetCollateral>, enabled: bool) -> Result<()> {
+        process_set_collateral(ctx, enabled)
+    }
+
+    pub fn mint_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+        process_mint_receipt(ctx, shares)
+    }
+
+    pub fn redeem_receipt(ctx: Context<ReceiptTransfer>, shares: u64) -> Result<()> {
+        process_redeem_receipt(ctx, shares)
+    }
+
+    pub fn flash_borrow(ctx: Context<FlashLoan>, amount: u64) -> Result<()> {
+        process_flash_borrow(ctx, amount)
+    }
+
+    pub fn flash_repay(ctx: Context<FlashLoan>) -> Result<()> {
+        process_flash_repay(ctx)
+    }
+
+    pub fn writeoff_bad_debt(ctx: Context<WriteoffBadDebt>) -> Result<()> {
+        process_writeoff_bad_debt(ctx)
     }
 }
@@ -0,0 +1,79 @@
+use anchor_lang::prelude::*;
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+
+use crate::error::ErrorCode;
+use crate::state::{Bank, OraclePriceMode, OracleType};
+
+// 预言机返回的现货价格和EMA价格
+#[derive(Clone, Copy, Debug)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub ema_price: i64,
+    pub exponent: i32,
+    pub mode: OraclePriceMode,
+}
+
+impl OraclePrice {
+    // 现货价格
+    pub fn spot(&self) -> u64 {
+        self.price as u64
+    }
+
+    // 抵押物按较低的价格计算: price - conf 或 min(spot, ema)
+    pub fn collateral_price(&self) -> Result<u64> {
+        let price = match self.mode {
+            OraclePriceMode::Confidence => self.spot().saturating_sub(self.conf),
+            OraclePriceMode::Ema => self.price.min(self.ema_price).max(0) as u64,
+        };
+        require!(price > 0, ErrorCode::InvalidPrice);
+        Ok(price)
+    }
+
+    // 借款按较高的价格计算: price + conf 或 max(spot, ema)
+    pub fn liability_price(&self) -> Result<u64> {
+        match self.mode {
+            OraclePriceMode::Confidence => self
+                .spot()
+                .checked_add(self.conf)
+                .ok_or(ErrorCode::MathOverflow.into()),
+            OraclePriceMode::Ema => Ok(self.price.max(self.ema_price) as u64),
+        }
+    }
+
+    // 置信区间占价格的比例, 单位为bps
+    pub fn confidence_bps(&self) -> u64 {
+        ((self.conf as u128 * 10_000) / self.price as u128).min(u64::MAX as u128) as u64
+    }
+}
+
+// 读取bank配置的预言机价格
+// get_price_no_older_than 会校验price_update的feed id与bank配置的一致, 并且价格不早于max_age
+pub fn get_price(bank: &Bank, price_update: &PriceUpdateV2) -> Result<OraclePrice> {
+    match bank.oracle.oracle_type {
+        OracleType::PythPull => {
+            let price = price_update.get_price_no_older_than(
+                &Clock::get()?,
+                bank.oracle.max_age,
+                &bank.oracle.feed_id,
+            )?;
+            require!(price.price > 0, ErrorCode::InvalidPrice);
+            Ok(OraclePrice {
+                price: price.price,
+                conf: price.conf,
+                ema_price: price_update.price_message.ema_price,
+                exponent: price.exponent,
+                mode: bank.oracle.price_mode,
+            })
+        }
+    }
+}
+
+// 借款和取款等会增加风险的操作要求置信区间不能过宽
+pub fn check_confidence(bank: &Bank, price: &OraclePrice) -> Result<()> {
+    require!(
+        price.confidence_bps() <= bank.oracle.max_confidence,
+        ErrorCode::OracleConfidenceTooWide
+    );
+    Ok(())
+}
@@ -1,27 +1,277 @@
 use anchor_lang::prelude::*;
 
+use crate::constants::{
+    MAX_BANKS, MAX_BORROW_RATE, MAX_EMODE_CATEGORIES, MAX_POSITIONS, MIN_SUPPLY_INDEX,
+    SECONDS_PER_YEAR, WAD,
+};
+use crate::error::ErrorCode;
+use crate::health::usd_value;
+use crate::oracle::get_price;
+use crate::utils::{compound_interest, load_bank_and_price, mul_div_down, mul_div_up, to_u64};
+
+// 整个借贷市场的根账户, 保存管理员和所有已上架的bank
+#[account]
+#[derive(InitSpace)]
+pub struct LendingMarket {
+    pub admin: Pubkey,
+    pub guardian: Pubkey, //可以暂停/恢复操作的紧急权限, 与admin分开
+    pub pause_flags: u8,
+    #[max_len(MAX_BANKS)]
+    pub banks: Vec<BankEntry>,
+    #[max_len(MAX_EMODE_CATEGORIES)]
+    pub emode_categories: Vec<EmodeCategory>,
+}
+
+impl LendingMarket {
+    pub fn is_admin_or_guardian(&self, key: &Pubkey) -> bool {
+        *key == self.admin || *key == self.guardian
+    }
+
+    // market或bank任意一层暂停了该操作都会被拒绝
+    pub fn check_not_paused(&self, bank: &Bank, operation: u8) -> Result<()> {
+        require!(
+            (self.pause_flags | bank.pause_flags) & operation == 0,
+            ErrorCode::OperationPaused
+        );
+        Ok(())
+    }
+
+    pub fn add_bank(&mut self, entry: BankEntry) -> Result<()> {
+        require!(self.banks.len() < MAX_BANKS, ErrorCode::TooManyBanks);
+        self.banks.push(entry);
+        Ok(())
+    }
+
+    pub fn find_bank_mut(&mut self, bank: &Pubkey) -> Result<&mut BankEntry> {
+        self.banks
+            .iter_mut()
+            .find(|entry| entry.bank == *bank)
+            .ok_or(ErrorCode::BankNotListed.into())
+    }
+
+    // id为0表示不使用e-mode
+    pub fn emode_category(&self, id: u8) -> Option<&EmodeCategory> {
+        if id == 0 {
+            return None;
+        }
+        self.emode_categories
+            .iter()
+            .find(|category| category.id == id)
+    }
+
+    // 新增类别, 已存在的类别则更新参数
+    pub fn set_emode_category(&mut self, category: EmodeCategory) -> Result<()> {
+        category.validate()?;
+        match self
+            .emode_categories
+            .iter_mut()
+            .find(|existing| existing.id == category.id)
+        {
+            Some(existing) => *existing = category,
+            None => {
+                require!(
+                    self.emode_categories.len() < MAX_EMODE_CATEGORIES,
+                    ErrorCode::TooManyEmodeCategories
+                );
+                self.emode_categories.push(category);
+            }
+        }
+        Ok(())
+    }
+}
+
+// 相关性高的资产(例如SOL和SOL LST, 或者稳定币)组成的类别, 用户选择该类别后,
+// 类别内的bank使用这里更高的风险参数代替bank自己的参数, 比例均以bps表示
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct EmodeCategory {
+    pub id: u8,
+    pub max_ltv: u64,
+    pub liquidation_threshold: u64,
+    pub liquidation_bonus: u64,
+}
+
+impl EmodeCategory {
+    pub fn validate(&self) -> Result<()> {
+        require!(self.id != 0, ErrorCode::InvalidEmodeCategory);
+        require!(
+            self.max_ltv < self.liquidation_threshold && self.liquidation_threshold <= 10_000,
+            ErrorCode::InvalidLtvConfig
+        );
+        require!(
+            self.liquidation_threshold as u128 * (10_000 + self.liquidation_bonus as u128)
+                <= 10_000 * 10_000,
+            ErrorCode::InvalidLiquidationBonus
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, InitSpace)]
+pub struct BankEntry {
+    pub bank: Pubkey,
+    pub mint: Pubkey,
+    pub oracle: OracleConfig,
+}
+
 #[account]
 #[derive(InitSpace)]
 pub struct User {
     pub owner: Pubkey,
-    pub deposited_sol: u64,
-    pub deposited_sol_shares: u64,
-    pub borrowed_sol: u64,
-    pub borrowed_sol_shares: u64,
-    pub deposited_usdc: u64,
-    pub deposited_usdc_shares: u64,
-    pub borrowed_usdc: u64,
-    pub borrowed_usdc_shares: u64,
-    pub usdc_address: Pubkey, //保存usdc mint account的地址
-    pub last_updated: i64,
-    pub last_updated_borrow: i64,
+    #[max_len(MAX_POSITIONS)]
+    pub positions: Vec<Position>, //每个bank对应一个position
+    pub isolated_bank: Pubkey, //作为唯一抵押物的隔离资产bank, 为默认值时不处于隔离模式
+    pub emode_category: u8,    //用户选择的e-mode类别, 为0表示不使用
+}
+
+impl User {
+    pub fn find_position(&self, bank: &Pubkey) -> Option<&Position> {
+        self.positions
+            .iter()
+            .find(|position| position.bank == *bank)
+    }
+
+    pub fn find_position_mut(&mut self, bank: &Pubkey) -> Result<&mut Position> {
+        self.positions
+            .iter_mut()
+            .find(|position| position.bank == *bank)
+            .ok_or(ErrorCode::PositionNotFound.into())
+    }
+
+    pub fn get_or_create_position(&mut self, bank: &Pubkey) -> Result<&mut Position> {
+        if let Some(index) = self.positions.iter().position(|p| p.bank == *bank) {
+            return Ok(&mut self.positions[index]);
+        }
+        require!(
+            self.positions.len() < MAX_POSITIONS,
+            ErrorCode::TooManyPositions
+        );
+        self.positions.push(Position {
+            bank: *bank,
+            collateral_enabled: true,
+            ..Default::default()
+        });
+        Ok(self.positions.last_mut().unwrap())
+    }
+
+    pub fn has_borrows(&self) -> bool {
+        self.positions
+            .iter()
+            .any(|position| position.borrow_shares > 0)
+    }
+
+    pub fn has_deposits(&self) -> bool {
+        self.positions
+            .iter()
+            .any(|position| position.deposit_shares > 0)
+    }
+
+    // 只统计作为抵押物的存款
+    pub fn has_collateral(&self) -> bool {
+        self.positions
+            .iter()
+            .any(|position| position.deposit_shares > 0 && position.collateral_enabled)
+    }
+
+    // 用户当前是否处于隔离模式: 隔离资产仍作为抵押物, 或者在隔离模式下产生的借款还没有还清.
+    // 隔离资产被清算完后借款仍计入该隔离资产的总借款, 直到还清或核销
+    pub fn isolated_collateral(&self) -> Option<Pubkey> {
+        if self.isolated_bank == Pubkey::default() {
+            return None;
+        }
+        let has_collateral = self
+            .find_position(&self.isolated_bank)
+            .is_some_and(|position| position.deposit_shares > 0 && position.collateral_enabled);
+        (has_collateral || self.has_borrows()).then_some(self.isolated_bank)
+    }
+
+    // 已经退出隔离模式时清除记录的隔离资产bank
+    fn clear_inactive_isolation(&mut self) {
+        if self.isolated_collateral().is_none() {
+            self.isolated_bank = Pubkey::default();
+        }
+    }
+
+    // 隔离资产只能单独作为抵押物, 处于隔离模式时其他存款也不能作为抵押物.
+    // 已有借款时不能进入隔离模式, 否则这些借款没有计入隔离资产的总借款
+    pub fn can_enable_collateral(&self, bank: &Pubkey, asset_tier: AssetTier) -> bool {
+        match asset_tier {
+            AssetTier::Isolated => {
+                self.isolated_collateral() == Some(*bank)
+                    || (!self.has_borrows()
+                        && !self.positions.iter().any(|position| {
+                            position.bank != *bank
+                                && position.deposit_shares > 0
+                                && position.collateral_enabled
+                        }))
+            }
+            AssetTier::Cross => self
+                .isolated_collateral()
+                .is_none_or(|isolated| isolated == *bank),
+        }
+    }
+
+    // 设置position是否作为抵押物, 启用隔离资产时进入隔离模式
+    pub fn set_collateral_enabled(
+        &mut self,
+        bank: &Pubkey,
+        asset_tier: AssetTier,
+        enabled: bool,
+    ) -> Result<()> {
+        self.clear_inactive_isolation();
+        if enabled {
+            require!(
+                self.can_enable_collateral(bank, asset_tier),
+                ErrorCode::IsolationModeConflict
+            );
+            if asset_tier == AssetTier::Isolated {
+                self.isolated_bank = *bank;
+            }
+        }
+        self.find_position_mut(bank)?.collateral_enabled = enabled;
+        Ok(())
+    }
+
+    // 存款时使用的position: 第一次存入时, 与隔离模式冲突的存款不作为抵押物
+    pub fn get_or_create_deposit_position(
+        &mut self,
+        bank: &Pubkey,
+        asset_tier: AssetTier,
+    ) -> Result<&mut Position> {
+        self.clear_inactive_isolation();
+        let can_enable = self.can_enable_collateral(bank, asset_tier);
+        let has_deposit = self.get_or_create_position(bank)?.deposit_shares > 0;
+        if !has_deposit {
+            if can_enable && asset_tier == AssetTier::Isolated {
+                self.isolated_bank = *bank;
+            }
+            self.find_position_mut(bank)?.collateral_enabled = can_enable;
+        }
+        self.find_position_mut(bank)
+    }
+
+    // 存款和借款都为0的position不再占用位置
+    pub fn remove_empty_positions(&mut self) {
+        self.positions
+            .retain(|position| position.deposit_shares > 0 || position.borrow_shares > 0);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct Position {
+    pub bank: Pubkey,
+    pub deposit_shares: u64,
+    pub deposit_index: u128, //上次操作时bank的supply_index快照
+    pub borrow_shares: u64,
+    pub borrow_index: u128,       //上次操作时bank的borrow_index快照
+    pub collateral_enabled: bool, //为false时存款只赚取利息, 不计入抵押物也不能被清算
 }
 
 #[account]
-#[derive(InitSpace)]
+#[derive(InitSpace, Default)]
 pub struct Bank {
-    pub authority: Pubkey,
+    pub market: Pubkey,
     pub mint_address: Pubkey,
+    pub mint_decimals: u8,
     pub total_depoists: u64,
     pub total_deposit_shares: u64,
     pub total_borrowed: u64,
@@ -31,5 +281,739 @@ pub struct Bank {
     pub liquidation_close_factor: u64, //一在触发清算时，清算人可以一次性清算的最大债务百分比
     pub max_ltv: u64, //Maximum Loan-to-Value Ratio 通过抵押物的价值能借出的最大金额
     pub last_updated: i64,
-    pub interest_rate: u64, //年化收益率
+    pub interest_rate_model: InterestRateModel,
+    pub reserve_factor: u64, //借款利息中归协议所有的比例, 单位为bps
+    pub supply_index: u128,  //存款累计利率指数, 以WAD表示, 初始为1
+    pub borrow_index: u128,  //借款累计利率指数, 以WAD表示, 初始为1
+    pub total_reserves: u64, //已累计但尚未提取的协议收入, 不属于存款人
+    pub oracle: OracleConfig,
+    pub pause_flags: u8,
+    pub supply_cap: u64,            //total_depoists上限, 为0表示不限制
+    pub borrow_cap: u64,            //total_borrowed上限, 为0表示不限制
+    pub supply_cap_usd: u64,        //总存款价值上限(美元), 为0表示不限制
+    pub borrow_cap_usd: u64,        //总借款价值上限(美元), 为0表示不限制
+    pub max_liquidation_bonus: u64, //动态清算奖励的上限, 为0时使用固定的liquidation_bonus
+    pub flash_loan_fee: u64,        //闪电贷手续费(bps)
+    pub flash_loan_amount: u64,     //当前交易中尚未归还的闪电贷数量
+    pub asset_tier: AssetTier,
+    pub borrowable_in_isolation: bool, //隔离模式的用户是否可以借出该资产, 只能开放稳定币
+    pub isolated_debt_ceiling: u64,    //以该隔离资产为抵押的总借款上限(美元), 为0表示不限制
+    pub isolated_debt: u128,           //以该隔离资产为抵押的总借款, 按稳定币面值以WAD表示的美元
+    pub emode_category: u8,            //所属的e-mode类别, 为0表示不属于任何类别
+    pub insolvent: bool, //坏账超过协议收入和所有存款后为true, 不再接受存款直到admin重新上架
+    pub is_stablecoin: bool, //锚定1美元的稳定币, 隔离模式的借款按面值计入总借款
+}
+
+impl Bank {
+    // 将存款和借款的累计利率指数推进到当前时间, 并同步总存款/总借款
+    pub fn accrue_interest(&mut self, now: i64) -> Result<()> {
+        let time_diff = now.saturating_sub(self.last_updated);
+        if time_diff <= 0 {
+            return Ok(());
+        }
+
+        let utilization = self.utilization()?;
+        let borrow_rate = self.interest_rate_model.borrow_rate(utilization)?;
+        let supply_rate =
+            self.interest_rate_model
+                .supply_rate(borrow_rate, utilization, self.reserve_factor)?;
+
+        // 年化利率 -> 每秒利率
+        let borrow_growth =
+            compound_interest(borrow_rate / SECONDS_PER_YEAR as u128, time_diff as u64)?;
+        let supply_growth =
+            compound_interest(supply_rate / SECONDS_PER_YEAR as u128, time_diff as u64)?;
+        self.supply_index = mul_div_down(self.supply_index, supply_growth, WAD)?;
+        self.borrow_index = mul_div_up(self.borrow_index, borrow_growth, WAD)?;
+
+        // 新产生的借款利息中reserve_factor部分计入协议收入
+        let previous_borrowed = self.total_borrowed;
+        self.total_depoists = self.deposit_amount(self.total_deposit_shares)?;
+        self.total_borrowed = self.borrow_amount(self.total_borrowed_shares)?;
+        let interest = self.total_borrowed.saturating_sub(previous_borrowed);
+        let reserves = mul_div_down(interest as u128, self.reserve_factor as u128, 10_000)?;
+        self.total_reserves = self
+            .total_reserves
+            .checked_add(to_u64(reserves)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.last_updated = now;
+        Ok(())
+    }
+
+    pub fn config(&self) -> BankConfig {
+        BankConfig {
+            max_ltv: self.max_ltv,
+            liquidation_threshold: self.liquidation_threshold,
+            liquidation_bonus: self.liquidation_bonus,
+            max_liquidation_bonus: self.max_liquidation_bonus,
+            liquidation_close_factor: self.liquidation_close_factor,
+            reserve_factor: self.reserve_factor,
+            flash_loan_fee: self.flash_loan_fee,
+            interest_rate_model: self.interest_rate_model,
+            supply_cap: self.supply_cap,
+            borrow_cap: self.borrow_cap,
+            supply_cap_usd: self.supply_cap_usd,
+            borrow_cap_usd: self.borrow_cap_usd,
+            asset_tier: self.asset_tier,
+            borrowable_in_isolation: self.borrowable_in_isolation,
+            isolated_debt_ceiling: self.isolated_debt_ceiling,
+            emode_category: self.emode_category,
+            is_stablecoin: self.is_stablecoin,
+        }
+    }
+
+    pub fn set_config(&mut self, config: &BankConfig) {
+        self.max_ltv = config.max_ltv;
+        self.liquidation_threshold = config.liquidation_threshold;
+        self.liquidation_bonus = config.liquidation_bonus;
+        self.max_liquidation_bonus = config.max_liquidation_bonus;
+        self.liquidation_close_factor = config.liquidation_close_factor;
+        self.reserve_factor = config.reserve_factor;
+        self.flash_loan_fee = config.flash_loan_fee;
+        self.interest_rate_model = config.interest_rate_model;
+        self.supply_cap = config.supply_cap;
+        self.borrow_cap = config.borrow_cap;
+        self.supply_cap_usd = config.supply_cap_usd;
+        self.borrow_cap_usd = config.borrow_cap_usd;
+        self.asset_tier = config.asset_tier;
+        self.borrowable_in_isolation = config.borrowable_in_isolation;
+        self.isolated_debt_ceiling = config.isolated_debt_ceiling;
+        self.emode_category = config.emode_category;
+        self.is_stablecoin = config.is_stablecoin;
+    }
+
+    // 存款之后检查总存款是否超过上限, 设置了美元上限时需要在remaining_accounts中传入该bank的 [bank, price_update]
+    pub fn check_supply_cap(
+        &self,
+        bank_key: &Pubkey,
+        remaining_accounts: &[AccountInfo],
+    ) -> Result<()> {
+        if self.supply_cap > 0 {
+            require!(
+                self.total_depoists <= self.supply_cap,
+                ErrorCode::SupplyCapExceeded
+            );
+        }
+        if self.supply_cap_usd > 0 {
+            let value = self.total_value(self.total_depoists, bank_key, remaining_accounts)?;
+            require!(
+                value <= self.supply_cap_usd as u128 * WAD,
+                ErrorCode::SupplyCapExceeded
+            );
+        }
+        Ok(())
+    }
+
+    // 借款之后检查总借款是否超过上限
+    pub fn check_borrow_cap(
+        &self,
+        bank_key: &Pubkey,
+        remaining_accounts: &[AccountInfo],
+    ) -> Result<()> {
+        if self.borrow_cap > 0 {
+            require!(
+                self.total_borrowed <= self.borrow_cap,
+                ErrorCode::BorrowCapExceeded
+            );
+        }
+        if self.borrow_cap_usd > 0 {
+            let value = self.total_value(self.total_borrowed, bank_key, remaining_accounts)?;
+            require!(
+                value <= self.borrow_cap_usd as u128 * WAD,
+                ErrorCode::BorrowCapExceeded
+            );
+        }
+        Ok(())
+    }
+
+    fn total_value(
+        &self,
+        amount: u64,
+        bank_key: &Pubkey,
+        remaining_accounts: &[AccountInfo],
+    ) -> Result<u128> {
+        let (_, price_update) = load_bank_and_price(remaining_accounts, bank_key)?;
+        let price = get_price(self, &price_update)?;
+        usd_value(amount, price.spot(), price.exponent, self.mint_decimals)
+    }
+
+    // 核销坏账: 从总借款中移除, 先用协议收入弥补, 剩余部分按比例降低supply_index, 由所有存款人共同承担.
+    // supply_index 低于MIN_SUPPLY_INDEX时标记bank资不抵债
+    // 返回存款人实际承担的损失数量
+    pub fn socialize_loss(&mut self, shares: u64, amount: u64) -> Result<u64> {
+        self.total_borrowed_shares -= shares;
+        self.total_borrowed = self.total_borrowed.saturating_sub(amount);
+
+        let covered = amount.min(self.total_reserves);
+        self.total_reserves -= covered;
+        let loss = (amount - covered).min(self.total_depoists);
+        if loss == 0 {
+            return Ok(0);
+        }
+        // supply_index = supply_index * (total_depoists - loss) / total_depoists
+        self.supply_index = mul_div_down(
+            self.supply_index,
+            (self.total_depoists - loss) as u128,
+            self.total_depoists as u128,
+        )?;
+        self.total_depoists = self.deposit_amount(self.total_deposit_shares)?;
+        if self.supply_index < MIN_SUPPLY_INDEX {
+            self.insolvent = true;
+        }
+        Ok(loss)
+    }
+
+    // 清算奖励(bps): max_liquidation_bonus为0时使用固定的liquidation_bonus,
+    // 否则奖励等于健康因子低于1的部分, 限制在 [liquidation_bonus, max_liquidation_bonus] 之间
+    pub fn liquidation_bonus_for(&self, health_factor: u64) -> u64 {
+        if self.max_liquidation_bonus == 0 {
+            return self.liquidation_bonus;
+        }
+        10_000u64
+            .saturating_sub(health_factor)
+            .clamp(self.liquidation_bonus, self.max_liquidation_bonus)
+    }
+
+    // 闪电贷手续费中reserve_factor部分计入协议收入, 其余通过提高supply_index分给存款人
+    pub fn distribute_fee(&mut self, fee: u64) -> Result<()> {
+        let reserves = to_u64(mul_div_down(
+            fee as u128,
+            self.reserve_factor as u128,
+            10_000,
+        )?)?;
+        // 没有存款人时全部计入协议收入
+        let reserves = if self.total_depoists == 0 {
+            fee
+        } else {
+            reserves
+        };
+        self.total_reserves = self
+            .total_reserves
+            .checked_add(reserves)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let to_depositors = fee - reserves;
+        if to_depositors > 0 {
+            self.supply_index = mul_div_down(
+                self.supply_index,
+                self.total_depoists as u128 + to_depositors as u128,
+                self.total_depoists as u128,
+            )?;
+            self.total_depoists = self.deposit_amount(self.total_deposit_shares)?;
+        }
+        Ok(())
+    }
+
+    // 隔离模式的用户借款之后, 以该隔离资产为抵押的总借款不能超过上限
+    pub fn add_isolated_debt(&mut self, value: u128) -> Result<()> {
+        self.isolated_debt = self
+            .isolated_debt
+            .checked_add(value)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if self.isolated_debt_ceiling > 0 {
+            require!(
+                self.isolated_debt <= self.isolated_debt_ceiling as u128 * WAD,
+                ErrorCode::IsolatedDebtCeilingExceeded
+            );
+        }
+        Ok(())
+    }
+
+    pub fn reduce_isolated_debt(&mut self, value: u128) {
+        self.isolated_debt = self.isolated_debt.saturating_sub(value);
+    }
+
+    // 资金利用率 = total_borrowed / total_depoists, 以WAD表示, 最大为1
+    pub fn utilization(&self) -> Result<u128> {
+        if self.total_depoists == 0 {
+            return Ok(0);
+        }
+        Ok(mul_div_down(
+            self.total_borrowed as u128,
+            WAD,
+            self.total_depoists as u128,
+        )?
+        .min(WAD))
+    }
+
+    // 存款份额对应的资产数量(向下取整)
+    pub fn deposit_amount(&self, shares: u64) -> Result<u64> {
+        to_u64(mul_div_down(shares as u128, self.supply_index, WAD)?)
+    }
+
+    // 存入amount应得的份额(向下取整)
+    pub fn deposit_shares_for(&self, amount: u64) -> Result<u64> {
+        to_u64(mul_div_down(amount as u128, WAD, self.supply_index)?)
+    }
+
+    // 取出amount需要扣除的份额(向上取整)
+    pub fn withdraw_shares_for(&self, amount: u64) -> Result<u64> {
+        to_u64(mul_div_up(amount as u128, WAD, self.supply_index)?)
+    }
+
+    // 借款份额对应的债务数量(向上取整)
+    pub fn borrow_amount(&self, shares: u64) -> Result<u64> {
+        to_u64(mul_div_up(shares as u128, self.borrow_index, WAD)?)
+    }
+
+    // 借出amount需要记入的借款份额(向上取整)
+    pub fn borrow_shares_for(&self, amount: u64) -> Result<u64> {
+        to_u64(mul_div_up(amount as u128, WAD, self.borrow_index)?)
+    }
+
+    // 偿还amount可以抵消的借款份额(向下取整)
+    pub fn repay_shares_for(&self, amount: u64) -> Result<u64> {
+        to_u64(mul_div_down(amount as u128, WAD, self.borrow_index)?)
+    }
+}
+
+// bank可以由管理员调整的风险参数, 比例均以bps表示
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct BankConfig {
+    pub max_ltv: u64,
+    pub liquidation_threshold: u64,
+    pub liquidation_bonus: u64,
+    pub max_liquidation_bonus: u64,
+    pub liquidation_close_factor: u64,
+    pub reserve_factor: u64,
+    pub flash_loan_fee: u64,
+    pub interest_rate_model: InterestRateModel,
+    pub supply_cap: u64,
+    pub borrow_cap: u64,
+    pub supply_cap_usd: u64,
+    pub borrow_cap_usd: u64,
+    pub asset_tier: AssetTier,
+    pub borrowable_in_isolation: bool,
+    pub isolated_debt_ceiling: u64,
+    pub emode_category: u8,
+    pub is_stablecoin: bool,
+}
+
+impl BankConfig {
+    pub fn validate(&self) -> Result<()> {
+        // max_ltv < liquidation_threshold <= 100%, 不能作为抵押物的资产两者都为0
+        require!(
+            self.liquidation_threshold <= 10_000
+                && (self.max_ltv < self.liquidation_threshold
+                    || (self.max_ltv == 0 && self.liquidation_threshold == 0)),
+            ErrorCode::InvalidLtvConfig
+        );
+        // 动态清算奖励的上限不能低于liquidation_bonus
+        require!(
+            self.max_liquidation_bonus == 0 || self.max_liquidation_bonus >= self.liquidation_bonus,
+            ErrorCode::InvalidLiquidationBonus
+        );
+        // 清算时抵押物要足够支付清算奖励: liquidation_threshold * (1 + bonus) <= 100%
+        require!(
+            self.liquidation_threshold as u128
+                * (10_000 + self.liquidation_bonus.max(self.max_liquidation_bonus) as u128)
+                <= 10_000 * 10_000,
+            ErrorCode::InvalidLiquidationBonus
+        );
+        require!(
+            self.liquidation_close_factor > 0 && self.liquidation_close_factor <= 10_000,
+            ErrorCode::InvalidCloseFactor
+        );
+        require!(
+            self.reserve_factor <= 10_000,
+            ErrorCode::InvalidReserveFactor
+        );
+        require!(
+            self.flash_loan_fee <= 10_000,
+            ErrorCode::InvalidFlashLoanFee
+        );
+        // 隔离模式的借款按面值计入隔离资产的总借款, 只有稳定币可以在隔离模式下借出
+        require!(
+            !self.borrowable_in_isolation || self.is_stablecoin,
+            ErrorCode::InvalidIsolationConfig
+        );
+        self.interest_rate_model.validate()
+    }
+}
+
+// update_bank_config 的参数, 为None的字段保持不变
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct BankConfigArgs {
+    pub max_ltv: Option<u64>,
+    pub liquidation_threshold: Option<u64>,
+    pub liquidation_bonus: Option<u64>,
+    pub max_liquidation_bonus: Option<u64>,
+    pub liquidation_close_factor: Option<u64>,
+    pub reserve_factor: Option<u64>,
+    pub flash_loan_fee: Option<u64>,
+    pub interest_rate_model: Option<InterestRateModel>,
+    pub supply_cap: Option<u64>,
+    pub borrow_cap: Option<u64>,
+    pub supply_cap_usd: Option<u64>,
+    pub borrow_cap_usd: Option<u64>,
+    pub asset_tier: Option<AssetTier>,
+    pub borrowable_in_isolation: Option<bool>,
+    pub isolated_debt_ceiling: Option<u64>,
+    pub emode_category: Option<u8>,
+    pub is_stablecoin: Option<bool>,
+}
+
+impl BankConfigArgs {
+    pub fn apply(&self, config: &BankConfig) -> BankConfig {
+        BankConfig {
+            max_ltv: self.max_ltv.unwrap_or(config.max_ltv),
+            liquidation_threshold: self
+                .liquidation_threshold
+                .unwrap_or(config.liquidation_threshold),
+            liquidation_bonus: self.liquidation_bonus.unwrap_or(config.liquidation_bonus),
+            max_liquidation_bonus: self
+                .max_liquidation_bonus
+                .unwrap_or(config.max_liquidation_bonus),
+            liquidation_close_factor: self
+                .liquidation_close_factor
+                .unwrap_or(config.liquidation_close_factor),
+            reserve_factor: self.reserve_factor.unwrap_or(config.reserve_factor),
+            flash_loan_fee: self.flash_loan_fee.unwrap_or(config.flash_loan_fee),
+            interest_rate_model: self
+                .interest_rate_model
+                .unwrap_or(config.interest_rate_model),
+            supply_cap: self.supply_cap.unwrap_or(config.supply_cap),
+            borrow_cap: self.borrow_cap.unwrap_or(config.borrow_cap),
+            supply_cap_usd: self.supply_cap_usd.unwrap_or(config.supply_cap_usd),
+            borrow_cap_usd: self.borrow_cap_usd.unwrap_or(config.borrow_cap_usd),
+            asset_tier: self.asset_tier.unwrap_or(config.asset_tier),
+            borrowable_in_isolation: self
+                .borrowable_in_isolation
+                .unwrap_or(config.borrowable_in_isolation),
+            isolated_debt_ceiling: self
+                .isolated_debt_ceiling
+                .unwrap_or(config.isolated_debt_ceiling),
+            emode_category: self.emode_category.unwrap_or(config.emode_category),
+            is_stablecoin: self.is_stablecoin.unwrap_or(config.is_stablecoin),
+        }
+    }
+}
+
+// 拐点利率模型, 所有参数均为年化, 单位为bps
+// 利用率 <= optimal_utilization 时: base_rate + slope1 * U / optimal
+// 利用率 >  optimal_utilization 时: base_rate + slope1 + slope2 * (U - optimal) / (1 - optimal)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct InterestRateModel {
+    pub base_rate: u64,
+    pub slope1: u64,
+    pub slope2: u64,
+    pub optimal_utilization: u64,
+}
+
+impl InterestRateModel {
+    // 借款年化利率, 以WAD表示
+    pub fn borrow_rate(&self, utilization: u128) -> Result<u128> {
+        let optimal = bps_to_wad(self.optimal_utilization);
+        let base_rate = bps_to_wad(self.base_rate);
+        let slope1 = bps_to_wad(self.slope1);
+        let slope2 = bps_to_wad(self.slope2);
+
+        if utilization <= optimal {
+            let variable = mul_div_down(slope1, utilization, optimal)?;
+            Ok(base_rate + variable)
+        } else {
+            let excess = utilization - optimal;
+            let variable = mul_div_down(slope2, excess, WAD - optimal)?;
+            Ok(base_rate + slope1 + variable)
+        }
+    }
+
+    // 存款年化利率 = 借款利率 * 利用率 * (1 - reserve_factor), 以WAD表示
+    pub fn supply_rate(
+        &self,
+        borrow_rate: u128,
+        utilization: u128,
+        reserve_factor: u64,
+    ) -> Result<u128> {
+        let gross = mul_div_down(borrow_rate, utilization, WAD)?;
+        mul_div_down(gross, (10_000 - reserve_factor) as u128, 10_000)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.optimal_utilization > 0 && self.optimal_utilization < 10_000,
+            ErrorCode::InvalidInterestRateModel
+        );
+        require!(
+            self.base_rate as u128 + self.slope1 as u128 + self.slope2 as u128
+                <= MAX_BORROW_RATE as u128,
+            ErrorCode::InvalidInterestRateModel
+        );
+        Ok(())
+    }
+}
+
+fn bps_to_wad(bps: u64) -> u128 {
+    bps as u128 * WAD / 10_000
+}
+
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub enum OracleType {
+    #[default]
+    PythPull,
+}
+
+// Cross: 可以和其他资产一起作为抵押物; Isolated: 只能单独作为抵押物, 并且只能借出borrowable_in_isolation的资产
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub enum AssetTier {
+    #[default]
+    Cross,
+    Isolated,
+}
+
+// 抵押物和借款分别按保守的价格计算
+// Confidence: 抵押物 price - conf, 借款 price + conf
+// Ema: 抵押物 min(spot, ema), 借款 max(spot, ema)
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub enum OraclePriceMode {
+    #[default]
+    Confidence,
+    Ema,
+}
+
+// 每个bank自己的预言机配置, 读取价格时会校验price_update属于该bank
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, InitSpace)]
+pub struct OracleConfig {
+    pub oracle_type: OracleType,
+    pub feed_id: [u8; 32],
+    pub max_age: u64,        //价格最多允许过期的秒数
+    pub max_confidence: u64, //置信区间占价格的最大比例, 单位为bps, 超过时拒绝借款和取款
+    pub price_mode: OraclePriceMode,
+}
+
+impl OracleConfig {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.feed_id != [0u8; 32] && self.max_age > 0 && self.max_confidence <= 10_000,
+            ErrorCode::InvalidOracleConfig
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bank_with(deposits: u64, borrowed: u64) -> Bank {
+        Bank {
+            total_depoists: deposits,
+            total_deposit_shares: deposits,
+            total_borrowed: borrowed,
+            total_borrowed_shares: borrowed,
+            supply_index: WAD,
+            borrow_index: WAD,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn socialize_loss_lowers_supply_index_proportionally() {
+        let mut bank = bank_with(1_000, 400);
+        let loss = bank.socialize_loss(400, 400).unwrap();
+
+        assert_eq!(loss, 400);
+        assert_eq!(bank.supply_index, WAD * 6 / 10);
+        assert_eq!(bank.total_depoists, 600);
+        assert_eq!(bank.total_borrowed, 0);
+        assert_eq!(bank.total_borrowed_shares, 0);
+    }
+
+    #[test]
+    fn socialize_loss_uses_reserves_first() {
+        let mut bank = bank_with(1_000, 400);
+        bank.total_reserves = 100;
+        let loss = bank.socialize_loss(400, 400).unwrap();
+
+        assert_eq!(loss, 300);
+        assert_eq!(bank.total_reserves, 0);
+        assert_eq!(bank.supply_index, WAD * 7 / 10);
+        assert!(!bank.insolvent);
+    }
+
+    #[test]
+    fn socialize_loss_full_wipe_marks_bank_insolvent() {
+        let mut bank = bank_with(1_000, 1_000);
+        let loss = bank.socialize_loss(1_000, 1_500).unwrap();
+
+        // 旧份额不再有任何价值, 也不能再存入新的资金
+        assert_eq!(loss, 1_000);
+        assert_eq!(bank.supply_index, 0);
+        assert_eq!(bank.total_depoists, 0);
+        assert_eq!(bank.deposit_amount(1_000).unwrap(), 0);
+        assert!(bank.insolvent);
+    }
+
+    #[test]
+    fn socialize_loss_above_threshold_marks_bank_insolvent() {
+        let mut bank = bank_with(1_000_000, 999_500);
+        bank.socialize_loss(999_500, 999_500).unwrap();
+
+        // 指数反映真实的损失, 不保留下限
+        assert_eq!(bank.supply_index, WAD / 2_000);
+        assert_eq!(bank.total_depoists, 500);
+        assert!(bank.insolvent);
+    }
+
+    fn empty_user() -> User {
+        User {
+            owner: Pubkey::default(),
+            positions: vec![],
+            isolated_bank: Pubkey::default(),
+            emode_category: 0,
+        }
+    }
+
+    #[test]
+    fn isolation_persists_while_borrows_remain() {
+        let isolated = Pubkey::new_unique();
+        let cross = Pubkey::new_unique();
+        let debt = Pubkey::new_unique();
+        let mut user = empty_user();
+
+        user.get_or_create_deposit_position(&isolated, AssetTier::Isolated)
+            .unwrap()
+            .deposit_shares = 100;
+        user.get_or_create_position(&debt).unwrap().borrow_shares = 50;
+        assert_eq!(user.isolated_collateral(), Some(isolated));
+
+        // 隔离资产被全部清算后, 剩余借款仍属于隔离模式
+        user.find_position_mut(&isolated).unwrap().deposit_shares = 0;
+        user.remove_empty_positions();
+        assert_eq!(user.isolated_collateral(), Some(isolated));
+        assert!(!user.can_enable_collateral(&cross, AssetTier::Cross));
+
+        // 借款还清后退出隔离模式, 新的存款可以作为抵押物
+        user.find_position_mut(&debt).unwrap().borrow_shares = 0;
+        user.remove_empty_positions();
+        assert_eq!(user.isolated_collateral(), None);
+        assert!(
+            user.get_or_create_deposit_position(&cross, AssetTier::Cross)
+                .unwrap()
+                .collateral_enabled
+        );
+        assert_eq!(user.isolated_bank, Pubkey::default());
+    }
+
+    #[test]
+    fn cannot_enter_isolation_with_existing_borrows() {
+        let isolated = Pubkey::new_unique();
+        let debt = Pubkey::new_unique();
+        let mut user = empty_user();
+
+        user.get_or_create_position(&debt).unwrap().borrow_shares = 50;
+        assert!(!user.can_enable_collateral(&isolated, AssetTier::Isolated));
+    }
+
+    #[test]
+    fn lend_only_deposits_are_not_collateral() {
+        let bank = Pubkey::new_unique();
+        let mut user = empty_user();
+
+        let position = user.get_or_create_position(&bank).unwrap();
+        position.deposit_shares = 1;
+        position.collateral_enabled = false;
+        assert!(!user.has_collateral());
+
+        user.find_position_mut(&bank).unwrap().collateral_enabled = true;
+        assert!(user.has_collateral());
+    }
+
+    fn rate_model() -> InterestRateModel {
+        InterestRateModel {
+            base_rate: 100,
+            slope1: 400,
+            slope2: 6_000,
+            optimal_utilization: 8_000,
+        }
+    }
+
+    #[test]
+    fn borrow_rate_follows_the_kink() {
+        let model = rate_model();
+        let bps = WAD / 10_000;
+
+        assert_eq!(model.borrow_rate(0).unwrap(), 100 * bps);
+        // U = 40%: base + slope1 / 2
+        assert_eq!(model.borrow_rate(WAD * 4 / 10).unwrap(), 300 * bps);
+        // U = optimal: base + slope1
+        assert_eq!(model.borrow_rate(WAD * 8 / 10).unwrap(), 500 * bps);
+        // U = 90%: base + slope1 + slope2 / 2
+        assert_eq!(model.borrow_rate(WAD * 9 / 10).unwrap(), 3_500 * bps);
+        assert_eq!(model.borrow_rate(WAD).unwrap(), 6_500 * bps);
+    }
+
+    #[test]
+    fn supply_rate_excludes_reserve_factor() {
+        let model = rate_model();
+        let borrow_rate = model.borrow_rate(WAD / 2).unwrap();
+        let supply_rate = model.supply_rate(borrow_rate, WAD / 2, 1_000).unwrap();
+        // borrow * 50% * 90%
+        assert_eq!(supply_rate, borrow_rate * 45 / 100);
+    }
+
+    #[test]
+    fn interest_rate_model_rejects_invalid_kink() {
+        let mut model = rate_model();
+        model.optimal_utilization = 0;
+        assert!(model.validate().is_err());
+        model.optimal_utilization = 10_000;
+        assert!(model.validate().is_err());
+    }
+
+    #[test]
+    fn interest_rate_model_caps_max_rate() {
+        let mut model = rate_model();
+        model.slope2 = MAX_BORROW_RATE - 500;
+        assert!(model.validate().is_ok());
+        model.slope2 += 1;
+        assert!(model.validate().is_err());
+    }
+
+    #[test]
+    fn liquidation_bonus_is_fixed_without_max() {
+        let bank = Bank {
+            liquidation_bonus: 500,
+            ..Default::default()
+        };
+        assert_eq!(bank.liquidation_bonus_for(9_000), 500);
+        assert_eq!(bank.liquidation_bonus_for(0), 500);
+    }
+
+    #[test]
+    fn liquidation_bonus_scales_with_shortfall_within_bounds() {
+        let bank = Bank {
+            liquidation_bonus: 200,
+            max_liquidation_bonus: 1_000,
+            ..Default::default()
+        };
+        // 健康因子刚低于1时使用下限
+        assert_eq!(bank.liquidation_bonus_for(9_950), 200);
+        // 健康因子0.95 -> 5%
+        assert_eq!(bank.liquidation_bonus_for(9_500), 500);
+        // 超过上限时取上限
+        assert_eq!(bank.liquidation_bonus_for(5_000), 1_000);
+        assert_eq!(bank.liquidation_bonus_for(u64::MAX), 200);
+    }
+
+    #[test]
+    fn only_stablecoins_are_borrowable_in_isolation() {
+        let mut config = BankConfig {
+            max_ltv: 7_500,
+            liquidation_threshold: 8_000,
+            liquidation_close_factor: 5_000,
+            borrowable_in_isolation: true,
+            interest_rate_model: rate_model(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        config.is_stablecoin = true;
+        assert!(config.validate().is_ok());
+    }
 }
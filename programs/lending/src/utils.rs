@@ -1,13 +1,372 @@
 use anchor_lang::prelude::*;
-use std::f64::consts::E;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::system_program::{
+    self, allocate, assign, create_account, Allocate, Assign, CreateAccount, Transfer,
+};
+use anchor_spl::token::spl_token::{native_mint, state::Account as TokenAccount};
+use anchor_spl::token_interface::{
+    burn, close_account, initialize_account3, mint_to, sync_native, transfer_checked, Burn,
+    CloseAccount, InitializeAccount3, MintTo, SyncNative, TokenAccount as InterfaceTokenAccount,
+    TransferChecked,
+};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
-pub fn calculate_collateral_interest(
-    deposited: u64,
-    interest_rate: u64,
-    last_updated: i64,
+use crate::constants::WAD;
+use crate::error::ErrorCode;
+use crate::state::Bank;
+
+// a * b / c, 向下取整
+pub fn mul_div_down(a: u128, b: u128, c: u128) -> Result<u128> {
+    mul_div(a, b, c, false)
+}
+
+// a * b / c, 向上取整
+pub fn mul_div_up(a: u128, b: u128, c: u128) -> Result<u128> {
+    mul_div(a, b, c, true)
+}
+
+// a * b 溢出时把a和b按c拆开: a = a1 * c + a0, b = b1 * c + b0,
+// a * b / c = a1 * b1 * c + a1 * b0 + a0 * b1 + a0 * b0 / c, 只有结果本身溢出时才返回错误
+fn mul_div(a: u128, b: u128, c: u128, round_up: bool) -> Result<u128> {
+    require!(c != 0, ErrorCode::MathOverflow);
+    let div = |value: u128| {
+        if round_up {
+            value.div_ceil(c)
+        } else {
+            value / c
+        }
+    };
+    if let Some(product) = a.checked_mul(b) {
+        return Ok(div(product));
+    }
+    let (a1, a0, b1, b0) = (a / c, a % c, b / c, b % c);
+    a1.checked_mul(b1)
+        .and_then(|v| v.checked_mul(c))
+        .and_then(|v| v.checked_add(a1.checked_mul(b0)?))
+        .and_then(|v| v.checked_add(a0.checked_mul(b1)?))
+        .and_then(|v| v.checked_add(div(a0.checked_mul(b0)?)))
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+pub fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// 连续复利 e^(rt) 的定点近似, rate_per_second 以 WAD 表示, 返回值为以 WAD 表示的增长倍数
+// rt 较大时泰勒展开误差变大并且会溢出, 拆成n段每段为COMPOUND_STEP: e^(rt) = (e^step)^n * e^(rt - n * step)
+pub fn compound_interest(rate_per_second: u128, time_diff: u64) -> Result<u128> {
+    let x = rate_per_second
+        .checked_mul(time_diff as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let steps = x / COMPOUND_STEP;
+    if steps == 0 {
+        return taylor_exp(x);
+    }
+    let growth = pow_wad(taylor_exp(COMPOUND_STEP)?, steps)?;
+    mul_div_down(growth, taylor_exp(x % COMPOUND_STEP)?, WAD)
+}
+
+const COMPOUND_STEP: u128 = WAD / 10;
+
+// e^x 取泰勒展开的前四项: 1 + x + x^2/2 + x^3/6
+fn taylor_exp(x: u128) -> Result<u128> {
+    let x_squared = mul_div_down(x, x, WAD)?;
+    let x_cubed = mul_div_down(x_squared, x, WAD)?;
+
+    WAD.checked_add(x)
+        .and_then(|v| v.checked_add(x_squared / 2))
+        .and_then(|v| v.checked_add(x_cubed / 6))
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+// 以WAD表示的base的exponent次方, 快速幂
+fn pow_wad(mut base: u128, mut exponent: u128) -> Result<u128> {
+    let mut result = WAD;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_div_down(result, base, WAD)?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = mul_div_down(base, base, WAD)?;
+        }
+    }
+    Ok(result)
+}
+
+// remaining_accounts 按 [bank, price_update] 成对传入, 找到bank_key对应的一对并反序列化
+pub fn load_bank_and_price(
+    remaining_accounts: &[AccountInfo],
+    bank_key: &Pubkey,
+) -> Result<(Bank, PriceUpdateV2)> {
+    let pair = remaining_accounts
+        .chunks_exact(2)
+        .find(|pair| pair[0].key == bank_key)
+        .ok_or(ErrorCode::MissingRemainingAccount)?;
+    Ok((load_bank(&pair[0])?, load_price_update(&pair[1])?))
+}
+
+pub fn load_bank(account: &AccountInfo) -> Result<Bank> {
+    require_keys_eq!(*account.owner, crate::ID, ErrorCode::InvalidBankAccount);
+    Bank::try_deserialize(&mut &account.try_borrow_data()?[..])
+}
+
+pub fn load_price_update(account: &AccountInfo) -> Result<PriceUpdateV2> {
+    require_keys_eq!(
+        *account.owner,
+        pyth_solana_receiver_sdk::ID,
+        ErrorCode::InvalidPriceAccount
+    );
+    PriceUpdateV2::try_deserialize(&mut &account.try_borrow_data()?[..])
+}
+
+// 原生SOL的变体只能用于wSOL bank
+pub fn check_native_mint(mint: &Pubkey) -> Result<()> {
+    require_keys_eq!(*mint, native_mint::ID, ErrorCode::NotNativeMint);
+    Ok(())
+}
+
+// 将payer的lamports转入wSOL账户并同步为token余额
+pub fn wrap_sol<'info>(
+    payer: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let transfer_cpi_accounts = Transfer {
+        from: payer,
+        to: token_account.clone(),
+    };
+    system_program::transfer(
+        CpiContext::new(system_program, transfer_cpi_accounts),
+        amount,
+    )?;
+    sync_native(CpiContext::new(
+        token_program,
+        SyncNative {
+            account: token_account,
+        },
+    ))
+}
+
+// 将payer的wSOL账户中amount数量的wSOL解包成lamports返还给payer:
+// 先转入payer专用的临时wSOL账户(PDA地址, authority为payer)再关闭它, 不影响payer原有的wSOL
+#[allow(clippy::too_many_arguments)]
+pub fn unwrap_sol<'info>(
+    payer: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    temp_token_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    temp_token_account_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let payer_key = payer.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[b"native", payer_key.as_ref(), &[temp_token_account_bump]]];
+    let space = TokenAccount::LEN;
+    let rent = Rent::get()?.minimum_balance(space);
+    let current_lamports = temp_token_account.lamports();
+    if current_lamports == 0 {
+        create_account(
+            CpiContext::new_with_signer(
+                system_program,
+                CreateAccount {
+                    from: payer.clone(),
+                    to: temp_token_account.clone(),
+                },
+                signer_seeds,
+            ),
+            rent,
+            space as u64,
+            token_program.key,
+        )?;
+    } else {
+        // 有人提前向该地址转入lamports时create_account会失败, 补足租金后再分配空间
+        if current_lamports < rent {
+            system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    Transfer {
+                        from: payer.clone(),
+                        to: temp_token_account.clone(),
+                    },
+                ),
+                rent - current_lamports,
+            )?;
+        }
+        allocate(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                Allocate {
+                    account_to_allocate: temp_token_account.clone(),
+                },
+                signer_seeds,
+            ),
+            space as u64,
+        )?;
+        assign(
+            CpiContext::new_with_signer(
+                system_program,
+                Assign {
+                    account_to_assign: temp_token_account.clone(),
+                },
+                signer_seeds,
+            ),
+            token_program.key,
+        )?;
+    }
+    initialize_account3(CpiContext::new(
+        token_program.clone(),
+        InitializeAccount3 {
+            account: temp_token_account.clone(),
+            mint: mint.clone(),
+            authority: payer.clone(),
+        },
+    ))?;
+    transfer_checked(
+        CpiContext::new(
+            token_program.clone(),
+            TransferChecked {
+                from: token_account,
+                mint,
+                to: temp_token_account.clone(),
+                authority: payer.clone(),
+            },
+        ),
+        amount,
+        native_mint::DECIMALS,
+    )?;
+    let close_cpi_accounts = CloseAccount {
+        account: temp_token_account,
+        destination: payer.clone(),
+        authority: payer,
+    };
+    close_account(CpiContext::new(token_program, close_cpi_accounts))
+}
+
+// payer的wSOL账户为空时关闭, 租金返还给payer; 还有余额时保留, 不会把payer原有的wSOL解包
+pub fn close_if_empty<'info>(
+    payer: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    if token_balance(&token_account)? > 0 {
+        return Ok(());
+    }
+    let close_cpi_accounts = CloseAccount {
+        account: token_account,
+        destination: payer.clone(),
+        authority: payer,
+    };
+    close_account(CpiContext::new(token_program, close_cpi_accounts))
+}
+
+pub fn token_balance(token_account: &AccountInfo) -> Result<u64> {
+    let data = token_account.try_borrow_data()?;
+    Ok(InterfaceTokenAccount::try_deserialize(&mut &data[..])?.amount)
+}
+
+// 转入后treasury实际到账的数量, Token-2022 的transfer fee 会让实际到账少于转账数量
+pub fn received_amount(
+    token_account: &mut InterfaceAccount<InterfaceTokenAccount>,
+    balance_before: u64,
 ) -> Result<u64> {
-    let current_time = Clock::get()?.unix_timestamp;
-    let time_diff = current_time - last_updated;
-    let new_value = (deposited as f64 * E.powf(interest_rate as f64 * time_diff as f64)) as u64;
-    Ok(new_value)
+    token_account.reload()?;
+    token_account
+        .amount
+        .checked_sub(balance_before)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+// 按存款份额铸造receipt token到receipt_vault, receipt_mint 的authority是它自己
+pub fn mint_receipt_to_vault<'info>(
+    receipt_mint: AccountInfo<'info>,
+    receipt_vault: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    mint_key: &Pubkey,
+    receipt_mint_bump: u8,
+    shares: u64,
+) -> Result<()> {
+    let signer_seeds: &[&[&[u8]]] = &[&[b"receipt", mint_key.as_ref(), &[receipt_mint_bump]]];
+    let mint_cpi_accounts = MintTo {
+        mint: receipt_mint.clone(),
+        to: receipt_vault,
+        authority: receipt_mint,
+    };
+    mint_to(
+        CpiContext::new_with_signer(token_program, mint_cpi_accounts, signer_seeds),
+        shares,
+    )
+}
+
+// 从receipt_vault销毁与存款份额相同数量的receipt token
+pub fn burn_receipt_from_vault<'info>(
+    receipt_mint: AccountInfo<'info>,
+    receipt_vault: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    mint_key: &Pubkey,
+    receipt_vault_bump: u8,
+    shares: u64,
+) -> Result<()> {
+    let signer_seeds: &[&[&[u8]]] =
+        &[&[b"receipt_vault", mint_key.as_ref(), &[receipt_vault_bump]]];
+    let burn_cpi_accounts = Burn {
+        mint: receipt_mint,
+        from: receipt_vault.clone(),
+        authority: receipt_vault,
+    };
+    burn(
+        CpiContext::new_with_signer(token_program, burn_cpi_accounts, signer_seeds),
+        shares,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::SECONDS_PER_YEAR;
+
+    #[test]
+    fn compound_interest_is_one_without_time() {
+        assert_eq!(compound_interest(WAD / 1_000, 0).unwrap(), WAD);
+    }
+
+    #[test]
+    fn compound_interest_approximates_exp() {
+        // rt = 0.05: 1 + x + x^2/2 + x^3/6
+        let rate = WAD / 200_000;
+        assert_eq!(
+            compound_interest(rate, 10_000).unwrap(),
+            WAD + WAD / 20 + WAD / 800 + WAD / 48_000
+        );
+        // rt = 1 拆成10段: e ≈ 2.71818
+        let growth = compound_interest(WAD / 10_000, 10_000).unwrap();
+        assert!(growth > WAD * 2_718 / 1_000 && growth < WAD * 27_183 / 10_000);
+        // 利率很小时接近单利
+        let growth =
+            compound_interest(WAD / SECONDS_PER_YEAR as u128 / 20, SECONDS_PER_YEAR).unwrap();
+        assert!(growth > WAD + WAD / 20 && growth < WAD + WAD / 19);
+    }
+
+    #[test]
+    fn compound_interest_handles_large_exponent() {
+        // rt = 10, 例如1000%的年利率一年没有更新: e^10 ≈ 22026
+        let growth = compound_interest(WAD / 1_000, 10_000).unwrap();
+        assert!(growth > WAD * 21_900 && growth < WAD * 22_030);
+    }
+
+    #[test]
+    fn mul_div_handles_intermediate_overflow() {
+        let a = u128::MAX / 3;
+        assert_eq!(mul_div_down(a, 6, 9).unwrap(), a / 3 * 2);
+        assert_eq!(mul_div_up(a, WAD, WAD).unwrap(), a);
+        assert!(mul_div_down(u128::MAX, 2, 1).is_err());
+    }
+
+    #[test]
+    fn compound_interest_rejects_overflow() {
+        assert!(compound_interest(u128::MAX / 2, 3).is_err());
+    }
 }
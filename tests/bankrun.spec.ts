@@ -12,9 +12,17 @@ import {
   BanksClient,
   ProgramTestContext,
   AddedAccount,
+  Clock,
 } from "solana-bankrun";
 
-import { PublicKey, Keypair, Connection } from "@solana/web3.js";
+import {
+  PublicKey,
+  Keypair,
+  Connection,
+  Transaction,
+  BPF_LOADER_UPGRADEABLE_PROGRAM_ID,
+} from "@solana/web3.js";
+import { readFileSync } from "fs";
 
 import { Lending } from "../target/types/lending";
 import { BankrunContextWrapper } from "../bankrun-utils/bankrunConnection";
@@ -28,6 +36,8 @@ describe("Lending Bankrun Tests", async () => {
   let userAccount: PublicKey;
 
   let solTokenAccount: PublicKey;
+  let usdcBank: PublicKey;
+  let solBank: PublicKey;
   let provider: BankrunProvider;
   let program: Program<Lending>;
   let banksClient: BanksClient;
@@ -41,6 +51,33 @@ describe("Lending Bankrun Tests", async () => {
   context = await startAnchor("", [], []);
   provider = new BankrunProvider(context);
 
+  // init_market 只允许程序的升级权限账户调用, 这里把程序改为由payer作为升级权限的可升级程序
+  const programId = new PublicKey(IDL.address);
+  const [programData] = PublicKey.findProgramAddressSync(
+    [programId.toBuffer()],
+    BPF_LOADER_UPGRADEABLE_PROGRAM_ID
+  );
+  const programElf = readFileSync("target/deploy/lending.so");
+  const programAccountData = Buffer.alloc(36);
+  programAccountData.writeUInt32LE(2, 0);
+  programData.toBuffer().copy(programAccountData, 4);
+  const programDataHeader = Buffer.alloc(45);
+  programDataHeader.writeUInt32LE(3, 0);
+  programDataHeader.writeUInt8(1, 12);
+  context.payer.publicKey.toBuffer().copy(programDataHeader, 13);
+  context.setAccount(programId, {
+    lamports: 1_000_000_000,
+    data: programAccountData,
+    owner: BPF_LOADER_UPGRADEABLE_PROGRAM_ID,
+    executable: true,
+  });
+  context.setAccount(programData, {
+    lamports: 1_000_000_000,
+    data: Buffer.concat([programDataHeader, programElf]),
+    owner: BPF_LOADER_UPGRADEABLE_PROGRAM_ID,
+    executable: false,
+  });
+
   bankrunContextWrapper = new BankrunContextWrapper(context);
 
   const connection = bankrunContextWrapper.connection.toConnection();
@@ -126,18 +163,113 @@ describe("Lending Bankrun Tests", async () => {
     program.programId
   );
 
+  [usdcBank] = PublicKey.findProgramAddressSync(
+    [mintUSDC.toBuffer()],
+    program.programId
+  );
+
+  [solBank] = PublicKey.findProgramAddressSync(
+    [mintSOL.toBuffer()],
+    program.programId
+  );
+
+  // 健康度检查需要的 [bank, price_update] 账户对
+  const bankAndPriceAccounts = [
+    { pubkey: usdcBank, isSigner: false, isWritable: false },
+    {
+      pubkey: usdcUsdPriceFeedAccountPubkey,
+      isSigner: false,
+      isWritable: false,
+    },
+    { pubkey: solBank, isSigner: false, isWritable: false },
+    {
+      pubkey: solUsdPriceFeedAccountPubkey,
+      isSigner: false,
+      isWritable: false,
+    },
+  ];
+
+  // 交易必须因为指定的错误码失败
+  const expectError = async (tx: Promise<unknown>, errorName: string) => {
+    const error = IDL.errors.find(
+      (e: { name: string }) => e.name.toLowerCase() === errorName.toLowerCase()
+    );
+    try {
+      await tx;
+    } catch (err) {
+      const logs = (err as { logs?: string[] }).logs ?? [];
+      const text = `${err} ${logs.join("\n")}`;
+      assert.isTrue(
+        text.includes(errorName) ||
+          text.includes(`0x${error.code.toString(16)}`),
+        `expected ${errorName}, got ${text}`
+      );
+      return;
+    }
+    assert.fail(`expected ${errorName}`);
+  };
+
+  // update_bank_config 只修改不为null的字段
+  const configArgs = (args: object) => ({
+    maxLtv: null,
+    liquidationThreshold: null,
+    liquidationBonus: null,
+    maxLiquidationBonus: null,
+    liquidationCloseFactor: null,
+    reserveFactor: null,
+    flashLoanFee: null,
+    interestRateModel: null,
+    supplyCap: null,
+    borrowCap: null,
+    supplyCapUsd: null,
+    borrowCapUsd: null,
+    assetTier: null,
+    borrowableInIsolation: null,
+    isolatedDebtCeiling: null,
+    emodeCategory: null,
+    isStablecoin: null,
+    ...args,
+  });
+
   [solTokenAccount] = PublicKey.findProgramAddressSync(
     [Buffer.from("treasury"), mintSOL.toBuffer()],
     program.programId
   );
 
+  // 年化利率: 基础 0%, 拐点前斜率 4%, 拐点后斜率 75%, 最优利用率 80%
+  const interestRateModel = {
+    baseRate: new BN(0),
+    slope1: new BN(400),
+    slope2: new BN(7_500),
+    optimalUtilization: new BN(8_000),
+  };
+
+  // 每个bank的预言机配置: pyth feed id, 最大允许的价格过期时间, 置信区间上限(2%)
+  const oracleConfig = (feedId: string) => ({
+    oracleType: { pythPull: {} },
+    feedId: Array.from(Buffer.from(feedId.slice(2), "hex")),
+    maxAge: new BN(100),
+    maxConfidence: new BN(200),
+    priceMode: { confidence: {} },
+  });
+
   console.log("USDC Bank Account", usdcBankAccount.toBase58());
 
   console.log("SOL Bank Account", solBankAccount.toBase58());
 
+  it("Init Market", async () => {
+    const initMarketTx = await program.methods
+      .initMarket()
+      .accounts({ programData })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    console.log("Create Lending Market", initMarketTx);
+  });
+
   it("Test Init User", async () => {
     const initUserTx = await program.methods
-      .initUser(mintUSDC)
+      .initUser()
       .signers([signer])
       .rpc({ commitment: "confirmed" });
 
@@ -146,7 +278,15 @@ describe("Lending Bankrun Tests", async () => {
 
   it("Init USDC Bank", async () => {
     const initUSDCBankTx = await program.methods
-      .initBank(new BN(8_500), new BN(8_000), new BN(5_000), new BN(500))
+      .initBank(
+        new BN(8_500),
+        new BN(8_000),
+        new BN(5_000),
+        new BN(500),
+        interestRateModel,
+        new BN(1_000),
+        oracleConfig(USDC_PRICE_FEED_ID)
+      )
       .accounts({
         mint: mintUSDC,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -172,7 +312,15 @@ describe("Lending Bankrun Tests", async () => {
 
   it("Init SOL Bank", async () => {
     const initSOLBankTx = await program.methods
-      .initBank(new BN(8_500), new BN(8_000), new BN(5_000), new BN(500))
+      .initBank(
+        new BN(8_500),
+        new BN(8_000),
+        new BN(5_000),
+        new BN(500),
+        interestRateModel,
+        new BN(1_000),
+        oracleConfig(SOL_PRICE_FEED_ID)
+      )
       .accounts({
         mint: mintSOL,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -235,7 +383,188 @@ describe("Lending Bankrun Tests", async () => {
 
     const user = await program.account.user.fetch(userAccount);
 
-    assert.equal(user.depositedUsdc.toNumber(), 1000_000_000);
+    assert.isTrue(user.positions[0].bank.equals(usdcBank));
+    assert.equal(user.positions[0].depositShares.toNumber(), 1000_000_000);
+  });
+
+  it("Test Pause", async () => {
+    const PAUSE_DEPOSIT = 1 << 0;
+    // 两次失败的交易使用不同的数量, 避免交易签名重复
+    const depositUSDC = (amount: number) =>
+      program.methods
+        .deposit(new BN(amount))
+        .accounts({
+          mint: mintUSDC,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    // 暂停单个bank的存款
+    await program.methods
+      .setBankPauseFlags(PAUSE_DEPOSIT)
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+    let bank = await program.account.bank.fetch(usdcBank);
+    assert.equal(bank.pauseFlags, PAUSE_DEPOSIT);
+    await expectError(depositUSDC(1_000_000), "OperationPaused");
+
+    await program.methods
+      .setBankPauseFlags(0)
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    // 暂停整个市场的存款
+    await program.methods
+      .setMarketPauseFlags(PAUSE_DEPOSIT)
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+    await expectError(depositUSDC(2_000_000), "OperationPaused");
+
+    await program.methods
+      .setMarketPauseFlags(0)
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    bank = await program.account.bank.fetch(usdcBank);
+    assert.equal(bank.pauseFlags, 0);
+    const user = await program.account.user.fetch(userAccount);
+    assert.equal(user.positions[0].depositShares.toNumber(), 1000_000_000);
+  });
+
+  it("Test Flash Loan", async () => {
+    const flashBorrow = await program.methods
+      .flashBorrow(new BN(100_000_000))
+      .accounts({
+        mint: mintUSDC,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+    const flashRepay = await program.methods
+      .flashRepay()
+      .accounts({
+        mint: mintUSDC,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .instruction();
+
+    const flashLoan = await provider.sendAndConfirm(
+      new Transaction().add(flashBorrow, flashRepay),
+      [signer]
+    );
+
+    console.log("Flash Loan USDC", flashLoan);
+
+    const bank = await program.account.bank.fetch(usdcBank);
+    assert.equal(bank.flashLoanAmount.toNumber(), 0);
+  });
+
+  it("Test Receipt", async () => {
+    await program.methods
+      .mintReceipt(new BN(100_000_000))
+      .accounts({
+        mint: mintUSDC,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    let user = await program.account.user.fetch(userAccount);
+    assert.equal(user.positions[0].depositShares.toNumber(), 900_000_000);
+
+    await program.methods
+      .redeemReceipt(new BN(100_000_000))
+      .accounts({
+        mint: mintUSDC,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    user = await program.account.user.fetch(userAccount);
+    assert.equal(user.positions[0].depositShares.toNumber(), 1000_000_000);
+  });
+
+  it("Test Set Collateral", async () => {
+    await program.methods
+      .setCollateral(false)
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    let user = await program.account.user.fetch(userAccount);
+    assert.isFalse(user.positions[0].collateralEnabled);
+
+    await program.methods
+      .setCollateral(true)
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    user = await program.account.user.fetch(userAccount);
+    assert.isTrue(user.positions[0].collateralEnabled);
+  });
+
+  it("Test E-Mode", async () => {
+    await program.methods
+      .setEmodeCategory({
+        id: 1,
+        maxLtv: new BN(9_000),
+        liquidationThreshold: new BN(9_300),
+        liquidationBonus: new BN(200),
+      })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    await program.methods
+      .setUserEmode(1)
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    let user = await program.account.user.fetch(userAccount);
+    assert.equal(user.emodeCategory, 1);
+
+    await program.methods
+      .setUserEmode(0)
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    user = await program.account.user.fetch(userAccount);
+    assert.equal(user.emodeCategory, 0);
+  });
+
+  it("Test Liquidation Bonus Config", async () => {
+    // 动态清算奖励的上限不能低于下限
+    await expectError(
+      program.methods
+        .updateBankConfig(
+          configArgs({
+            liquidationBonus: new BN(500),
+            maxLiquidationBonus: new BN(300),
+          })
+        )
+        .accounts({ mint: mintUSDC })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "InvalidLiquidationBonus"
+    );
+
+    await program.methods
+      .updateBankConfig(
+        configArgs({
+          liquidationBonus: new BN(500),
+          maxLiquidationBonus: new BN(1_000),
+        })
+      )
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    const bank = await program.account.bank.fetch(usdcBank);
+    assert.equal(bank.liquidationBonus.toNumber(), 500);
+    assert.equal(bank.maxLiquidationBonus.toNumber(), 1_000);
   });
 
   it("Test Borrow", async () => {
@@ -243,16 +572,19 @@ describe("Lending Bankrun Tests", async () => {
       .borrow(new BN(2_000_000))
       .accounts({
         mint: mintSOL,
+        isolatedBank: null,
+        nativeTokenAccount: null,
         tokenProgram: TOKEN_PROGRAM_ID,
-        solOrUsdcPriceFeed: usdcUsdPriceFeedAccountPubkey,
       })
+      .remainingAccounts(bankAndPriceAccounts)
       .signers([signer])
       .rpc({ commitment: "confirmed" });
 
     console.log("Borrow SOL", borrowSOL);
 
     const user = await program.account.user.fetch(userAccount);
-    assert.equal(user.borrowedSol.toNumber(), 2_000_000);
+    assert.isTrue(user.positions[1].bank.equals(solBank));
+    assert.equal(user.positions[1].borrowShares.toNumber(), 2_000_000);
   });
 
   it("Test Repay", async () => {
@@ -260,6 +592,7 @@ describe("Lending Bankrun Tests", async () => {
       .repay(new BN(1_000_000))
       .accounts({
         mint: mintSOL,
+        isolatedBank: null,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([signer])
@@ -268,7 +601,66 @@ describe("Lending Bankrun Tests", async () => {
     console.log("Repay SOL", repaySOL);
 
     const user = await program.account.user.fetch(userAccount);
-    assert.equal(user.borrowedSol.toNumber(), 1_000_000);
+    assert.isAtMost(user.positions[1].borrowShares.toNumber(), 1_000_000);
+  });
+
+  it("Test Supply And Borrow Caps", async () => {
+    // 存款上限等于当前总存款, 再存入任何数量都会超过上限
+    let usdc = await program.account.bank.fetch(usdcBank);
+    await program.methods
+      .updateBankConfig(configArgs({ supplyCap: usdc.totalDepoists }))
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+    await expectError(
+      program.methods
+        .deposit(new BN(1_000_000))
+        .accounts({
+          mint: mintUSDC,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "SupplyCapExceeded"
+    );
+
+    let sol = await program.account.bank.fetch(solBank);
+    await program.methods
+      .updateBankConfig(configArgs({ borrowCap: sol.totalBorrowed }))
+      .accounts({ mint: mintSOL })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+    await expectError(
+      program.methods
+        .borrow(new BN(1_000))
+        .accounts({
+          mint: mintSOL,
+          isolatedBank: null,
+          nativeTokenAccount: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(bankAndPriceAccounts)
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "BorrowCapExceeded"
+    );
+
+    // 上限为0表示不限制
+    await program.methods
+      .updateBankConfig(configArgs({ supplyCap: new BN(0) }))
+      .accounts({ mint: mintUSDC })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+    await program.methods
+      .updateBankConfig(configArgs({ borrowCap: new BN(0) }))
+      .accounts({ mint: mintSOL })
+      .signers([signer])
+      .rpc({ commitment: "confirmed" });
+
+    usdc = await program.account.bank.fetch(usdcBank);
+    sol = await program.account.bank.fetch(solBank);
+    assert.equal(usdc.supplyCap.toNumber(), 0);
+    assert.equal(sol.borrowCap.toNumber(), 0);
   });
 
   it("Test Withdraw", async () => {
@@ -276,30 +668,144 @@ describe("Lending Bankrun Tests", async () => {
       .withdraw(new BN(50_000_000))
       .accounts({
         mint: mintUSDC,
+        nativeTokenAccount: null,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
+      .remainingAccounts(bankAndPriceAccounts)
       .signers([signer])
       .rpc({ commitment: "confirmed" });
 
     console.log("Withdraw USDC", withdrawUSDC);
 
     const user = await program.account.user.fetch(userAccount);
-    assert.equal(user.depositedUsdc.toNumber(), 950_000_000);
+    assert.isAtMost(user.positions[0].depositShares.toNumber(), 950_000_000);
   });
 
   it("test liquidate", async () => {
+    // 用户的抵押物远远超过借款, 健康因子大于1, 不能被清算
+    await expectError(
+      program.methods
+        .liquidate(new BN(500_000), new BN(0))
+        .accounts({
+          collateralMint: mintUSDC,
+          borrowedMint: mintSOL,
+          user: userAccount,
+          liquidator: signer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(bankAndPriceAccounts)
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "NotUnderCollateralized"
+    );
+
+    const user = await program.account.user.fetch(userAccount);
+    assert.isAtMost(user.positions[0].depositShares.toNumber(), 950_000_000);
+    assert.isAbove(user.positions[1].borrowShares.toNumber(), 0);
+  });
+
+  it("test liquidate rejects the same bank for collateral and debt", async () => {
+    await expectError(
+      program.methods
+        .liquidate(new BN(500_000), new BN(0))
+        .accounts({
+          collateralMint: mintUSDC,
+          borrowedMint: mintUSDC,
+          user: userAccount,
+          liquidator: signer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(bankAndPriceAccounts)
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "SameBankLiquidation"
+    );
+  });
+
+  it("Test Writeoff Bad Debt", async () => {
+    // 用户在其他bank中仍有存款, 借款可以通过清算收回, 不能核销
+    const before = await program.account.bank.fetch(solBank);
+    await expectError(
+      program.methods
+        .writeoffBadDebt()
+        .accounts({
+          mint: mintSOL,
+          isolatedBank: null,
+          user: userAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" }),
+      "UserHasCollateral"
+    );
+
+    const after = await program.account.bank.fetch(solBank);
+    assert.isTrue(after.supplyIndex.eq(before.supplyIndex));
+    assert.isTrue(after.totalBorrowedShares.eq(before.totalBorrowedShares));
+  });
+
+  // 时间前进一年后价格会过期, 需要预言机的测试都要放在这个测试之前
+  it("Test Interest Rate Kink", async () => {
+    const SOLTokenAccount = await createAccount(
+      // @ts-ignores
+      banksClient,
+      signer,
+      mintSOL,
+      signer.publicKey
+    );
+    await mintTo(
+      // @ts-ignores
+      banksClient,
+      signer,
+      mintSOL,
+      SOLTokenAccount,
+      signer,
+      10 * 10 ** 6
+    );
+
+    // 存入SOL使利用率约为90%, 超过80%的拐点
+    let bank = await program.account.bank.fetch(solBank);
+    const deposit = bank.totalBorrowed.muln(10).divn(9);
     await program.methods
-      .liquidate()
+      .deposit(deposit)
       .accounts({
-        collateralMint: mintUSDC,
-        borrowedMint: mintSOL,
-        solPriceFeed: solUsdPriceFeedAccountPubkey,
-        usdcPriceFeed: usdcUsdPriceFeedAccountPubkey,
-        liquidator: signer.publicKey,
+        mint: mintSOL,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([signer])
       .rpc({ commitment: "confirmed" });
+
+    bank = await program.account.bank.fetch(solBank);
+    const borrowIndexBefore = bank.borrowIndex;
+    const supplyIndexBefore = bank.supplyIndex;
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(365 * 24 * 60 * 60)
+      )
+    );
+
+    await program.methods
+      .refreshBank()
+      .accounts({ mint: mintSOL })
+      .rpc({ commitment: "confirmed" });
+
+    bank = await program.account.bank.fetch(solBank);
+    const growth = (after: BN, before: BN) =>
+      after.muln(10_000).div(before).toNumber() / 10_000;
+    // 借款年利率 = 4% + 75% * (90% - 80%) / 20% = 41.5%, 没有拐点时只有约4.5%
+    const borrowGrowth = growth(bank.borrowIndex, borrowIndexBefore);
+    assert.isAbove(borrowGrowth, 1.45);
+    assert.isBelow(borrowGrowth, 1.55);
+    // 存款利率 = 借款利率 * 利用率 * (1 - reserve factor)
+    const supplyGrowth = growth(bank.supplyIndex, supplyIndexBefore);
+    assert.isAbove(supplyGrowth, 1.3);
+    assert.isBelow(supplyGrowth, borrowGrowth);
   });
 
   // it("test liquidate by chaning sol price", async () => {
@@ -331,11 +837,10 @@ describe("Lending Bankrun Tests", async () => {
   //     .accounts({
   //       collateralMint: mintUSDC,
   //       borrowedMint: mintSOL,
-  //       solPriceFeed: solUsdPriceFeedAccountPubkey,
-  //       usdcPriceFeed: usdcUsdPriceFeedAccountPubkey,
   //       liquidator: signer.publicKey,
   //       tokenProgram: TOKEN_PROGRAM_ID,
   //     })
+  //     .remainingAccounts(bankAndPriceAccounts)
   //     .signers([signer])
   //     .rpc({ commitment: "confirmed" });
 